@@ -1,4 +1,7 @@
+use rust_decimal::Decimal;
+use storage::errors::TransactionError;
 use thiserror::Error;
+use transaction::client::Client;
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
@@ -10,6 +13,57 @@ pub enum Error {
     AccountLocked,
     #[error("amount cannot be negative")]
     AmountCannotBeNegative,
+    #[error("amount must be positive")]
+    AmountNotPositive,
+    #[error("insufficient funds")]
+    InsufficientFunds,
+    #[error("amount is missing")]
+    MissingAmount,
+    #[error("amount is larger than the {0} left for this transaction")]
+    AmountAboveLimit(Decimal),
+    #[error("transfer destination is missing")]
+    MissingDestination,
+    #[error("cannot transfer to the same client")]
+    SelfTransfer,
+    #[error("reason is missing")]
+    MissingReason,
+    #[error("operator is missing")]
+    MissingOperator,
+    #[error("account is not locked")]
+    AccountNotLocked,
+    #[error("client {0} has no position")]
+    ClientNotFound(Client),
     #[error("unknown")]
     Unknown,
 }
+
+impl Error {
+    /// Stable, machine readable identifier of the error, suitable for reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Storage(e) => e.code(),
+            Error::AccountLocked => "account_locked",
+            Error::AmountCannotBeNegative => "amount_cannot_be_negative",
+            Error::AmountNotPositive => "amount_not_positive",
+            Error::InsufficientFunds => "insufficient_funds",
+            Error::MissingAmount => "missing_amount",
+            Error::AmountAboveLimit(_) => "amount_above_limit",
+            Error::MissingDestination => "missing_destination",
+            Error::SelfTransfer => "self_transfer",
+            Error::MissingReason => "missing_reason",
+            Error::MissingOperator => "missing_operator",
+            Error::AccountNotLocked => "account_not_locked",
+            Error::ClientNotFound(_) => "client_not_found",
+            Error::Unknown => "unknown",
+        }
+    }
+}
+
+impl From<TransactionError<Error>> for Error {
+    fn from(error: TransactionError<Error>) -> Self {
+        match error {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        }
+    }
+}
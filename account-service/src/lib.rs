@@ -1,16 +1,23 @@
 use std::{
     fmt::{Debug, Formatter},
+    path::Path,
     result,
 };
 
 use async_trait::async_trait;
-use futures::{pin_mut, StreamExt};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
 use rust_decimal::Decimal;
-use storage::{errors::Data, sled::Sled, Error as StorageError};
-use tracing::instrument;
+use storage::{
+    entities::{client_position_prefix, ClientLock, ClientTransaction},
+    errors::{Conflictable, Data, Unabortable},
+    memory::Memory,
+    sled::Sled,
+    RetryPolicy, Storage, StorageTransaction,
+};
+use tracing::{instrument, warn};
 use transaction::{
-    client::{Client, ClientPosition},
-    Transaction, TransactionType,
+    client::{Client, ClientPosition, Currency, Unlock},
+    StateChange, Transaction, TransactionId, TransactionRecord, TransactionState, TransactionType,
 };
 
 use crate::{
@@ -24,10 +31,41 @@ const DECIMAL_PRECISION: u32 = 4;
 
 #[async_trait]
 /// Storage is just an abstraction of what would be a database.
-pub trait Service: Debug + Sync {
+pub trait Service: Debug + Send + Sync {
     async fn add_transaction(&self, transaction: Transaction) -> Result<Transaction>;
-    async fn get_transaction(&self, client: Client, transaction_id: u32) -> Result<Transaction>;
-    async fn get_clients_positions(&self) -> Result<Vec<ClientPosition>>;
+    /// Adds `transactions` in order, each row seeing the effect of the previous ones, and returns
+    /// the outcome of every row at the same index. Refused rows do not prevent the others from
+    /// being stored.
+    async fn add_transactions(&self, transactions: Vec<Transaction>) -> Vec<Result<Transaction>>;
+    /// Gets a transaction along with the kind of row that opened it and every row applied to it.
+    /// Only `client` who opened it, or received it through a transfer, can get it.
+    async fn get_transaction(
+        &self,
+        client: Client,
+        transaction_id: TransactionId,
+    ) -> Result<TransactionRecord>;
+    /// Streams every transaction `client` opened or received through a transfer, sorted by
+    /// transaction id.
+    fn list_transactions(&self, client: Client) -> BoxStream<'_, Result<TransactionRecord>>;
+    /// Streams every client position sorted by client.
+    fn get_clients_positions(&self) -> BoxStream<'_, Result<ClientPosition>>;
+    /// Streams every position of `client`, one per currency, sorted by currency.
+    fn list_client_positions(&self, client: Client) -> BoxStream<'_, Result<ClientPosition>>;
+    /// Gets the position of `client` in `currency`, [`Error::ClientNotFound`] if it has none.
+    async fn get_client_position(
+        &self,
+        client: Client,
+        currency: Option<Currency>,
+    ) -> Result<ClientPosition>;
+    /// Unlocks the position of `client` in `currency` after a chargeback locked it, keeping who
+    /// did it and why in [`ClientPosition::last_unlock`]. Until then every position of `client`
+    /// refuses rows.
+    async fn unlock(
+        &self,
+        client: Client,
+        currency: Option<Currency>,
+        unlock: Unlock,
+    ) -> Result<ClientPosition>;
 }
 
 /// Operation is used to mimic atomic operations on a database for example.
@@ -38,86 +76,436 @@ pub struct Operation {
     pub locked: Decimal,
 }
 
-pub struct ServiceImpl {
-    storage: Sled,
+pub struct ServiceImpl<S: Storage = Sled> {
+    storage: S,
+    fee_overdraft_limit: Decimal,
+    retry_policy: RetryPolicy,
 }
 
-impl ServiceImpl {
+impl ServiceImpl<Sled> {
     pub fn with_sled() -> Result<Self> {
         let storage = Sled::new()?;
-        Ok(Self { storage })
+        Ok(Self::with_storage(storage))
     }
 
-    /// This mimics atomic operations by using database's ability to do addition/subtraction without
-    /// having to fetch the value first, like:
-    /// update client set available = available + 30 where client_id = 1
-    async fn update_client_position(&self, transaction: &Transaction) -> Result<()> {
-        let amount = transaction
-            .amount
-            .expect("amount in this point should be filled");
-        let amount = amount.round_dp(DECIMAL_PRECISION);
-        if amount.is_sign_negative() {
-            return Err(AmountCannotBeNegative);
+    /// Same as [`ServiceImpl::with_sled`] but keeps positions and transactions in a database at
+    /// `path`, so a later run picks up where this one stopped.
+    pub fn with_sled_at<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let storage = Sled::open(path)?;
+        Ok(Self::with_storage(storage))
+    }
+}
+
+impl ServiceImpl<Memory> {
+    /// Keeps everything in memory, nothing is written to disk.
+    pub fn in_memory() -> Self {
+        Self::with_storage(Memory::new())
+    }
+}
+
+impl<S: Storage> ServiceImpl<S> {
+    pub fn with_storage(storage: S) -> Self {
+        Self {
+            storage,
+            fee_overdraft_limit: Decimal::ZERO,
+            retry_policy: Default::default(),
+        }
+    }
+
+    /// How far below zero a fee may take the available funds of a client, zero by default.
+    pub fn with_fee_overdraft_limit(mut self, fee_overdraft_limit: Decimal) -> Self {
+        self.fee_overdraft_limit = fee_overdraft_limit;
+        self
+    }
+
+    /// Replaces the default [`RetryPolicy`] used when concurrent writers conflict.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Applies `transaction` inside a storage transaction so the transaction and its effect on the
+    /// client position are either both stored or none of them is.
+    ///
+    /// Everything is read before anything is written, so an error leaves `storage` untouched.
+    fn apply_transaction<T: StorageTransaction>(
+        &self,
+        storage: &T,
+        transaction: &Transaction,
+    ) -> result::Result<Transaction, Conflictable<Error>> {
+        if transaction.transaction_type == TransactionType::Unlock {
+            let unlock = Unlock {
+                operator: Self::required(&transaction.operator, Error::MissingOperator)?,
+                reason: Self::required(&transaction.reason, Error::MissingReason)?,
+            };
+            Self::unlock_position(storage, transaction.client, &transaction.currency, unlock)?;
+            return Ok(transaction.clone());
+        }
+        let existing = match storage.get(&TransactionRecord::from(transaction.clone())) {
+            Err(Unabortable::Data(Data::KeyNotFound(_))) => None,
+            Ok(old) => Some(old),
+            Err(e) => return Err(e.into()),
+        };
+        // Disputes and what follows them move funds in the currency of the transaction they refer
+        // to, whatever currency their own row has.
+        let currency = existing
+            .as_ref()
+            .map_or(&transaction.currency, |old| &old.transaction.currency);
+        // Rows for a locked account are refused before looking any further
+        Self::unlocked_position(storage, transaction.client, currency)?;
+        let opened = existing.is_none();
+        let (mut new_record, amount) = match existing {
+            None => {
+                if !Self::opens_transaction(transaction) {
+                    return Err(Data::TransactionNotFoundForClient(transaction.client).into());
+                }
+                let amount = match transaction.transaction_type {
+                    TransactionType::Adjustment => Self::rounded_amount(transaction.amount),
+                    _ => Self::checked_amount(transaction.amount),
+                }
+                .map_err(Conflictable::Abort)?;
+                if transaction.transaction_type == TransactionType::Adjustment {
+                    Self::required(&transaction.reason, Error::MissingReason)?;
+                }
+                if transaction.transaction_type == TransactionType::Transfer {
+                    match transaction.destination {
+                        None => return Err(Conflictable::Abort(Error::MissingDestination)),
+                        Some(destination) if destination == transaction.client => {
+                            return Err(Conflictable::Abort(Error::SelfTransfer))
+                        }
+                        Some(_) => {}
+                    }
+                }
+                // Fees and adjustments are ours, clients cannot dispute them
+                let disputable = match transaction.transaction_type {
+                    TransactionType::Fee | TransactionType::Adjustment => Decimal::ZERO,
+                    _ => amount,
+                };
+                let record = TransactionRecord {
+                    disputable,
+                    ..TransactionRecord::from(transaction.clone())
+                };
+                (record, amount)
+            }
+            Some(old) => {
+                let record = Self::merge_transaction(&old, transaction)?;
+                let amount = (record.held - old.held).abs();
+                (record, amount)
+            }
+        };
+        new_record.history.push(StateChange {
+            transaction_type: transaction.transaction_type.clone(),
+            state: Self::state_after(&transaction.transaction_type),
+            amount,
+            line: transaction.line,
+        });
+        // Lowest available funds the row may leave its client with, adjustments are trusted
+        let floor = match new_record.transaction.transaction_type {
+            TransactionType::Withdrawal | TransactionType::Transfer => Some(Decimal::ZERO),
+            TransactionType::Fee => Some(-self.fee_overdraft_limit),
+            _ => None,
+        };
+        let mut new_positions = vec![];
+        for change in Self::client_position_changes(&new_record, amount) {
+            let new_position =
+                match Self::unlocked_position(storage, change.client, &change.currency)? {
+                    Some(old) => Self::merge_client_position(&old, &change)?,
+                    None => change,
+                };
+            if new_position.client == transaction.client
+                && floor.is_some_and(|floor| new_position.available < floor)
+            {
+                return Err(Conflictable::Abort(Error::InsufficientFunds));
+            }
+            new_positions.push(new_position);
+        }
+        let locking = new_positions.iter().find(|position| position.locked);
+        if opened {
+            for client in [Some(transaction.client), new_record.transaction.destination]
+                .into_iter()
+                .flatten()
+            {
+                storage.insert(&ClientTransaction {
+                    client,
+                    transaction_id: transaction.transaction_id,
+                })?;
+            }
+        }
+        storage.insert(&new_record)?;
+        for new_position in &new_positions {
+            storage.insert(new_position)?;
+        }
+        if let Some(position) = locking {
+            storage.insert(&ClientLock {
+                client: position.client,
+                locked: true,
+            })?;
+        }
+        Ok(new_record.transaction)
+    }
+
+    /// Whether `error` refuses a row rather than reporting a storage failure. Those are raised
+    /// before the row writes anything, so the rows around it can still be committed.
+    fn is_refusal(error: &Data) -> bool {
+        matches!(
+            error,
+            Data::KeyNotFound(_)
+                | Data::TransactionNotFoundForClient(_)
+                | Data::InvalidTransition(..)
+        )
+    }
+
+    /// Gets the position of `client` in `currency`, `None` if it has none yet, refusing clients
+    /// frozen by a chargeback in any currency.
+    fn unlocked_position<T: StorageTransaction>(
+        storage: &T,
+        client: Client,
+        currency: &Option<Currency>,
+    ) -> result::Result<Option<ClientPosition>, Conflictable<Error>> {
+        match storage.get(&ClientLock {
+            client,
+            ..Default::default()
+        }) {
+            Err(Unabortable::Data(Data::KeyNotFound(_))) => {}
+            Ok(lock) if lock.locked => return Err(Conflictable::Abort(Error::AccountLocked)),
+            Ok(_) => {}
+            Err(e) => return Err(e.into()),
+        }
+        match storage.get(&ClientPosition {
+            client,
+            currency: currency.clone(),
+            ..Default::default()
+        }) {
+            Err(Unabortable::Data(Data::KeyNotFound(_))) => Ok(None),
+            Ok(position) if position.locked => Err(Conflictable::Abort(Error::AccountLocked)),
+            Ok(position) => Ok(Some(position)),
+            Err(e) => Err(e.into()),
         }
-        let mut client_position = ClientPosition {
-            client: transaction.client,
+    }
+
+    /// Unlocks a position, refusing those that are not locked or do not exist. Unlocks are not
+    /// transactions, so they do not take a transaction id.
+    fn unlock_position<T: StorageTransaction>(
+        storage: &T,
+        client: Client,
+        currency: &Option<Currency>,
+        unlock: Unlock,
+    ) -> result::Result<ClientPosition, Conflictable<Error>> {
+        let mut position = match storage.get(&ClientPosition {
+            client,
+            currency: currency.clone(),
             ..Default::default()
+        }) {
+            Err(Unabortable::Data(Data::KeyNotFound(_))) => {
+                return Err(Conflictable::Abort(Error::ClientNotFound(client)))
+            }
+            position => position?,
         };
-        match transaction.transaction_type {
-            TransactionType::Deposit => {
-                client_position.available = amount;
+        if !position.locked {
+            return Err(Conflictable::Abort(Error::AccountNotLocked));
+        }
+        position.locked = false;
+        position.last_unlock = Some(unlock);
+        storage.insert(&position)?;
+        storage.insert(&ClientLock {
+            client,
+            locked: false,
+        })?;
+        Ok(position)
+    }
+
+    /// Returns `field` unless it is missing or blank, in which case `error` is returned.
+    fn required(
+        field: &Option<String>,
+        error: Error,
+    ) -> result::Result<String, Conflictable<Error>> {
+        match field.as_deref().map(str::trim) {
+            Some(value) if !value.is_empty() => Ok(value.to_string()),
+            _ => Err(Conflictable::Abort(error)),
+        }
+    }
+
+    /// Rounds `amount` to the precision positions are kept with.
+    fn rounded_amount(amount: Option<Decimal>) -> Result<Decimal> {
+        let amount = amount.ok_or(Error::MissingAmount)?;
+        Ok(amount.round_dp(DECIMAL_PRECISION))
+    }
+
+    /// Same as [`ServiceImpl::rounded_amount`] but refusing negative amounts.
+    fn checked_amount(amount: Option<Decimal>) -> Result<Decimal> {
+        let amount = Self::rounded_amount(amount)?;
+        if amount.is_sign_negative() {
+            return Err(AmountCannotBeNegative);
+        }
+        Ok(amount)
+    }
+
+    /// Same as [`ServiceImpl::checked_amount`] but also refusing zero, which would only add
+    /// empty entries to the history of a transaction.
+    fn positive_amount(amount: Option<Decimal>) -> Result<Decimal> {
+        let amount = Self::checked_amount(amount)?;
+        if amount.is_zero() {
+            return Err(Error::AmountNotPositive);
+        }
+        Ok(amount)
+    }
+
+    /// This mimics atomic operations by using database's ability to do addition/subtraction without
+    /// having to fetch the value first, like:
+    /// update client set available = available + 30 where client_id = 1
+    ///
+    /// Disputing a deposit holds funds the client still has, while disputing a withdrawal holds
+    /// funds that already left, so the latter grows the total until it is resolved (the withdrawal
+    /// stands) or charged back (the client is credited). Transfers are disputed by their source and
+    /// hold funds at the destination, a chargeback returns them to the source.
+    fn client_position_changes(record: &TransactionRecord, amount: Decimal) -> Vec<ClientPosition> {
+        let transaction = &record.transaction;
+        let source = transaction.client;
+        // Transfers cannot be opened without a destination
+        let destination = transaction.destination.unwrap_or(source);
+        let zero = Decimal::ZERO;
+        let changes = match (&record.kind, &transaction.transaction_type) {
+            (_, TransactionType::Deposit) => vec![(source, amount, zero)],
+            (_, TransactionType::Withdrawal) => vec![(source, -amount, zero)],
+            (_, TransactionType::Transfer) => {
+                vec![(source, -amount, zero), (destination, amount, zero)]
             }
-            TransactionType::Withdrawal => {
-                client_position.available = -amount;
+            (_, TransactionType::Fee) => vec![(source, -amount, zero)],
+            (_, TransactionType::Adjustment) => vec![(source, amount, zero)],
+            (TransactionType::Deposit, TransactionType::Dispute) => vec![(source, -amount, amount)],
+            (TransactionType::Deposit, TransactionType::Resolve) => vec![(source, amount, -amount)],
+            (TransactionType::Deposit, TransactionType::Chargeback) => {
+                vec![(source, zero, -amount)]
             }
-            TransactionType::Dispute => {
-                client_position.held = amount;
+            (TransactionType::Withdrawal, TransactionType::Dispute) => vec![(source, zero, amount)],
+            (TransactionType::Withdrawal, TransactionType::Resolve) => {
+                vec![(source, zero, -amount)]
             }
-            TransactionType::Resolve => {
-                client_position.held = -amount;
+            (TransactionType::Withdrawal, TransactionType::Chargeback) => {
+                vec![(source, amount, -amount)]
+            }
+            (TransactionType::Transfer, TransactionType::Dispute) => {
+                vec![(destination, -amount, amount)]
             }
-            TransactionType::Chargeback => {
-                client_position.locked = true;
-                client_position.held = -amount;
-                client_position.available = -amount;
+            (TransactionType::Transfer, TransactionType::Resolve) => {
+                vec![(destination, amount, -amount)]
             }
+            (TransactionType::Transfer, TransactionType::Chargeback) => {
+                vec![(destination, zero, -amount), (source, amount, zero)]
+            }
+            (kind, _) => unreachable!("{} does not open transactions", kind),
         };
-        client_position.available -= client_position.held;
-        client_position.total += client_position.held + client_position.available;
-        self.storage
-            .create_or_update(client_position, Self::merge_client_position)?;
-        Ok(())
+        changes
+            .into_iter()
+            .map(|(client, available, held)| ClientPosition {
+                client,
+                currency: transaction.currency.clone(),
+                total: available + held,
+                available,
+                held,
+                // Chargebacks lock whoever disputed the transaction
+                locked: transaction.transaction_type == TransactionType::Chargeback
+                    && client == source,
+                fees: match transaction.transaction_type {
+                    TransactionType::Fee => amount,
+                    _ => zero,
+                },
+                adjustments: match transaction.transaction_type {
+                    TransactionType::Adjustment => amount,
+                    _ => zero,
+                },
+                last_unlock: None,
+            })
+            .collect()
     }
 
+    /// Applies a dispute, resolve or chargeback row to the transaction it refers to. Rows without
+    /// an amount act on everything they can: what is left to dispute for disputes and what is held
+    /// for resolves and chargebacks.
     fn merge_transaction(
-        old: &Transaction,
+        old: &TransactionRecord,
         new: &Transaction,
-    ) -> result::Result<Transaction, Data> {
-        if old.client != new.client {
-            return Err(Data::TransactionNotFoundForClient(new.client));
+    ) -> result::Result<TransactionRecord, Conflictable<Error>> {
+        if old.transaction.client != new.client {
+            return Err(Data::TransactionNotFoundForClient(new.client).into());
         }
         if !Self::can_transition(old, new) {
             return Err(Data::InvalidTransition(
-                old.transaction_type.to_string(),
+                old.transaction.transaction_type.to_string(),
                 new.transaction_type.to_string(),
-            ));
+            )
+            .into());
+        }
+        let limit = if new.transaction_type == TransactionType::Dispute {
+            old.disputable
+        } else {
+            old.held
+        };
+        let amount = match new.amount {
+            Some(_) => Self::positive_amount(new.amount).map_err(Conflictable::Abort)?,
+            None => limit,
+        };
+        if amount > limit {
+            return Err(Conflictable::Abort(Error::AmountAboveLimit(limit)));
         }
-        let old = old.clone();
-        Ok(Transaction {
-            transaction_type: new.transaction_type.clone(),
-            ..old
-        })
+        let mut record = old.clone();
+        record.transaction.transaction_type = new.transaction_type.clone();
+        match new.transaction_type {
+            TransactionType::Dispute => {
+                record.held += amount;
+                record.disputable -= amount;
+            }
+            TransactionType::Resolve => {
+                record.held -= amount;
+                record.disputable += amount;
+            }
+            TransactionType::Chargeback => record.held -= amount,
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Transfer
+            | TransactionType::Fee
+            | TransactionType::Adjustment
+            | TransactionType::Unlock => {
+                unreachable!("transactions are opened only once")
+            }
+        }
+        Ok(record)
     }
 
-    #[instrument(fields(old = %old.transaction_type, new = %new.transaction_type))]
-    fn can_transition(old: &Transaction, new: &Transaction) -> bool {
-        match old.transaction_type {
-            TransactionType::Deposit => new.transaction_type == TransactionType::Dispute,
-            TransactionType::Withdrawal => false,
-            TransactionType::Dispute => [TransactionType::Resolve, TransactionType::Chargeback]
-                .contains(&new.transaction_type),
-            TransactionType::Resolve => false,
-            TransactionType::Chargeback => false,
+    /// State the funds moved by a row of `transaction_type` end up in.
+    fn state_after(transaction_type: &TransactionType) -> TransactionState {
+        match transaction_type {
+            TransactionType::Dispute => TransactionState::Held,
+            TransactionType::Chargeback => TransactionState::ChargedBack,
+            _ => TransactionState::Available,
+        }
+    }
+
+    /// Whether `transaction` creates a new transaction id instead of referring to an existing one.
+    fn opens_transaction(transaction: &Transaction) -> bool {
+        matches!(
+            transaction.transaction_type,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Transfer
+                | TransactionType::Fee
+                | TransactionType::Adjustment
+        )
+    }
+
+    /// Disputes are possible while part of the amount was never disputed (or was resolved), while
+    /// resolves and chargebacks need funds held by earlier disputes.
+    #[instrument(fields(old = %old.transaction.transaction_type, new = %new.transaction_type))]
+    fn can_transition(old: &TransactionRecord, new: &Transaction) -> bool {
+        match new.transaction_type {
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Transfer
+            | TransactionType::Fee
+            | TransactionType::Adjustment
+            | TransactionType::Unlock => false,
+            TransactionType::Dispute => old.disputable > Decimal::ZERO,
+            TransactionType::Resolve | TransactionType::Chargeback => old.held > Decimal::ZERO,
         }
     }
 
@@ -129,12 +517,14 @@ impl ServiceImpl {
         output.total += new.total;
         output.available += new.available;
         output.held += new.held;
+        output.fees += new.fees;
+        output.adjustments += new.adjustments;
         output.locked = new.locked;
         Ok(output)
     }
 }
 
-impl Debug for ServiceImpl {
+impl<S: Storage> Debug for ServiceImpl<S> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str("ServiceImpl<")?;
         write!(f, "{}", self.storage)?;
@@ -143,52 +533,132 @@ impl Debug for ServiceImpl {
 }
 
 #[async_trait]
-impl Service for ServiceImpl {
+impl<S: Storage> Service for ServiceImpl<S> {
     #[instrument(skip_all, err)]
     async fn add_transaction(&self, transaction: Transaction) -> Result<Transaction> {
-        let client = self.storage.get(&ClientPosition {
-            client: transaction.client,
-            ..Default::default()
-        });
-        match client {
-            Err(StorageError::Data(Data::KeyNotFound(_))) => {}
-            Ok(client) => {
-                if client.locked {
-                    return Err(Error::AccountLocked);
+        let new_transaction = self
+            .retry_policy
+            .transaction(&self.storage, |storage| {
+                self.apply_transaction(storage, &transaction)
+            })
+            .await?;
+        Ok(new_transaction)
+    }
+
+    #[instrument(skip_all, fields(count = transactions.len()))]
+    async fn add_transactions(&self, transactions: Vec<Transaction>) -> Vec<Result<Transaction>> {
+        // A single storage transaction commits the writes of every row at once
+        let batch = self
+            .retry_policy
+            .transaction(&self.storage, |storage| {
+                transactions
+                    .iter()
+                    .map(
+                        |transaction| match self.apply_transaction(storage, transaction) {
+                            Ok(transaction) => Ok(Ok(transaction)),
+                            Err(Conflictable::Abort(e)) => Ok(Err(e)),
+                            Err(Conflictable::Data(e)) if Self::is_refusal(&e) => {
+                                Ok(Err(storage::Error::from(e).into()))
+                            }
+                            Err(e) => Err(e),
+                        },
+                    )
+                    .collect::<result::Result<Vec<_>, Conflictable<Error>>>()
+            })
+            .await;
+        match batch {
+            Ok(outcomes) => outcomes,
+            Err(e) => {
+                // Storage failures are not tied to a row, find out which rows still go through
+                warn!(error = %Error::from(e), "failed to add batch, adding rows one by one");
+                let mut outcomes = Vec::with_capacity(transactions.len());
+                for transaction in transactions {
+                    outcomes.push(self.add_transaction(transaction).await);
                 }
+                outcomes
             }
-            Err(e) => return Err(e.into()),
         }
-        let new_transaction = self
-            .storage
-            .create_or_update(transaction, Self::merge_transaction)?;
-        self.update_client_position(&new_transaction).await?;
-        Ok(new_transaction)
     }
 
     #[instrument]
-    async fn get_transaction(&self, client: Client, transaction_id: u32) -> Result<Transaction> {
-        let entity = self.storage.get(&Transaction {
+    async fn get_transaction(
+        &self,
+        client: Client,
+        transaction_id: TransactionId,
+    ) -> Result<TransactionRecord> {
+        let record = self.storage.get(&TransactionRecord::from(Transaction {
             client,
             transaction_id,
             ..Default::default()
-        })?;
-        Ok(entity)
+        }))?;
+        // Transaction ids are shared by every client, the record may belong to someone else
+        if record.transaction.client != client && record.transaction.destination != Some(client) {
+            return Err(storage::Error::from(Data::TransactionNotFoundForClient(client)).into());
+        }
+        Ok(record)
     }
 
     #[instrument]
-    async fn get_clients_positions(&self) -> Result<Vec<ClientPosition>> {
-        let mut output = vec![];
-        let list = self
-            .storage
+    fn list_transactions(&self, client: Client) -> BoxStream<'_, Result<TransactionRecord>> {
+        self.storage
+            .list::<ClientTransaction>(&ClientTransaction::client_prefix(client))
+            .map_err(Error::from)
+            .and_then(move |entry| async move {
+                let record = self.storage.get(&TransactionRecord::from(Transaction {
+                    client: entry.client,
+                    transaction_id: entry.transaction_id,
+                    ..Default::default()
+                }))?;
+                Ok(record)
+            })
+            .boxed()
+    }
+
+    #[instrument]
+    fn get_clients_positions(&self) -> BoxStream<'_, Result<ClientPosition>> {
+        self.storage
             .list::<ClientPosition>("client-position-")
-            .await;
-        pin_mut!(list);
-        while let Some(item) = list.next().await {
-            let item = item?;
+            .map_err(Error::from)
+            .boxed()
+    }
+
+    #[instrument]
+    fn list_client_positions(&self, client: Client) -> BoxStream<'_, Result<ClientPosition>> {
+        self.storage
+            .list::<ClientPosition>(&client_position_prefix(client))
+            .map_err(Error::from)
+            .boxed()
+    }
 
-            output.push(item);
+    #[instrument(err)]
+    async fn get_client_position(
+        &self,
+        client: Client,
+        currency: Option<Currency>,
+    ) -> Result<ClientPosition> {
+        match self.storage.get(&ClientPosition {
+            client,
+            currency,
+            ..Default::default()
+        }) {
+            Err(storage::Error::Data(Data::KeyNotFound(_))) => Err(Error::ClientNotFound(client)),
+            position => Ok(position?),
         }
-        Ok(output)
+    }
+
+    #[instrument(skip(unlock), fields(operator = %unlock.operator), err)]
+    async fn unlock(
+        &self,
+        client: Client,
+        currency: Option<Currency>,
+        unlock: Unlock,
+    ) -> Result<ClientPosition> {
+        let position = self
+            .retry_policy
+            .transaction(&self.storage, |storage| {
+                Self::unlock_position(storage, client, &currency, unlock.clone())
+            })
+            .await?;
+        Ok(position)
     }
 }
@@ -1,17 +1,31 @@
 use std::sync::Once;
 
-use account_service::{errors::Error::Storage, Service, ServiceImpl};
+use account_service::{
+    errors::Error::{
+        AccountLocked, AccountNotLocked, AmountCannotBeNegative, ClientNotFound, InsufficientFunds,
+        Storage,
+    },
+    Service, ServiceImpl,
+};
 use color_eyre::eyre::WrapErr;
-use storage::{errors::Data::TransactionNotFoundForClient, Error::Data};
+use futures::TryStreamExt;
+use storage::{
+    errors::Data::{KeyNotFound, TransactionNotFoundForClient},
+    memory::Memory,
+    Error::Data,
+};
 use tokio::test;
 use tracing_error::ErrorLayer;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Registry};
-use transaction::{client::ClientPosition, Transaction, TransactionType};
+use transaction::{
+    client::{ClientPosition, Unlock},
+    Transaction, TransactionState, TransactionType,
+};
 
 static TRACING: Once = Once::new();
 
-fn get_test_service() -> ServiceImpl {
-    ServiceImpl::with_sled().expect("failed to create service")
+fn get_test_service() -> ServiceImpl<Memory> {
+    ServiceImpl::in_memory()
 }
 
 fn get_test_transaction() -> Transaction {
@@ -20,6 +34,11 @@ fn get_test_transaction() -> Transaction {
         client: 10,
         transaction_id: 2,
         amount: Some(30.into()),
+        currency: None,
+        destination: None,
+        reason: None,
+        operator: None,
+        line: None,
     }
 }
 
@@ -35,7 +54,7 @@ async fn add_transaction() {
         .get_transaction(transaction.client, transaction.transaction_id)
         .await
         .expect("failed to get saved transaction");
-    assert_eq!(transaction, saved_transaction);
+    assert_eq!(transaction, saved_transaction.transaction);
 }
 
 #[test]
@@ -55,16 +74,21 @@ async fn client_position_incremental_deposits() {
             .expect("failed to save transaction");
         let positions = service
             .get_clients_positions()
+            .try_collect::<Vec<_>>()
             .await
             .expect("failed to get clients positions");
         assert_eq!(positions.len(), 1);
         let position = &positions[0];
         let expected = ClientPosition {
             client: 10,
+            currency: None,
             total: (total * i).into(),
             available: (available * i).into(),
             held: 0.into(),
             locked: false,
+            fees: 0.into(),
+            adjustments: 0.into(),
+            last_unlock: None,
         };
         assert_eq!(position, &expected);
     }
@@ -78,10 +102,14 @@ async fn client_position_dispute_and_solve() -> color_eyre::Result<()> {
 
     let expected = ClientPosition {
         client: 10,
+        currency: None,
         total: 30.into(),
         available: 30.into(),
         held: 0.into(),
         locked: false,
+        fees: 0.into(),
+        adjustments: 0.into(),
+        last_unlock: None,
     };
 
     let transactions = vec![
@@ -108,9 +136,12 @@ async fn client_position_dispute_and_solve() -> color_eyre::Result<()> {
             ..expected.clone()
         },
     ];
-    for (transaction, expected) in transactions.into_iter().zip(expectations.into_iter()) {
+    for (transaction, expected) in transactions.into_iter().zip(expectations) {
         service.add_transaction(transaction).await?;
-        let positions = service.get_clients_positions().await?;
+        let positions = service
+            .get_clients_positions()
+            .try_collect::<Vec<_>>()
+            .await?;
         assert_eq!(positions.len(), 1);
         let position = &positions[0];
 
@@ -139,10 +170,14 @@ async fn client_position_lock_account() {
 
     let expected = ClientPosition {
         client: 10,
+        currency: None,
         total: 30.into(),
         available: 30.into(),
         held: 0.into(),
         locked: false,
+        fees: 0.into(),
+        adjustments: 0.into(),
+        last_unlock: None,
     };
 
     let transactions = vec![
@@ -171,17 +206,14 @@ async fn client_position_lock_account() {
             ..expected.clone()
         },
     ];
-    for (i, (transaction, expected)) in transactions
-        .into_iter()
-        .zip(expectations_.into_iter())
-        .enumerate()
-    {
+    for (i, (transaction, expected)) in transactions.into_iter().zip(expectations_).enumerate() {
         service
             .add_transaction(transaction)
             .await
             .expect("failed to save transaction");
         let positions = service
             .get_clients_positions()
+            .try_collect::<Vec<_>>()
             .await
             .wrap_err("failed to get clients positions")
             .unwrap();
@@ -217,3 +249,573 @@ async fn duplicated_transaction() {
         ),
     };
 }
+
+#[test]
+async fn positions_survive_reopening_database() {
+    let directory = tempfile::tempdir().expect("failed to create database directory");
+    {
+        let service = ServiceImpl::with_sled_at(directory.path()).expect("failed to open service");
+        service
+            .add_transaction(get_test_transaction())
+            .await
+            .expect("failed to save transaction");
+    }
+
+    let service = ServiceImpl::with_sled_at(directory.path()).expect("failed to reopen service");
+    let positions = service
+        .get_clients_positions()
+        .try_collect::<Vec<_>>()
+        .await
+        .expect("failed to get clients positions");
+    assert_eq!(
+        positions,
+        vec![ClientPosition {
+            client: 10,
+            currency: None,
+            total: 30.into(),
+            available: 30.into(),
+            held: 0.into(),
+            locked: false,
+            fees: 0.into(),
+            adjustments: 0.into(),
+            last_unlock: None,
+        }]
+    );
+    let transaction = get_test_transaction();
+    service
+        .get_transaction(transaction.client, transaction.transaction_id)
+        .await
+        .expect("transaction should have been persisted");
+}
+
+#[test]
+async fn rejected_transaction_is_not_stored() {
+    let service = get_test_service();
+    let transaction = Transaction {
+        amount: Some((-30).into()),
+        ..get_test_transaction()
+    };
+    match service.add_transaction(transaction.clone()).await {
+        Err(AmountCannotBeNegative) => {}
+        other => panic!("negative amounts should be refused and not {:?}", other),
+    }
+    match service
+        .get_transaction(transaction.client, transaction.transaction_id)
+        .await
+    {
+        Err(Storage(Data(KeyNotFound(_)))) => {}
+        other => panic!("transaction should not have been stored, got {:?}", other),
+    }
+    let positions = service
+        .get_clients_positions()
+        .try_collect::<Vec<_>>()
+        .await
+        .expect("failed to get clients positions");
+    assert!(positions.is_empty());
+}
+
+#[test]
+async fn withdrawal_above_available_is_refused() {
+    let service = get_test_service();
+    service
+        .add_transaction(get_test_transaction())
+        .await
+        .expect("failed to save transaction");
+    let withdrawal = Transaction {
+        transaction_type: TransactionType::Withdrawal,
+        transaction_id: 3,
+        amount: Some(31.into()),
+        ..get_test_transaction()
+    };
+    match service.add_transaction(withdrawal.clone()).await {
+        Err(InsufficientFunds) => {}
+        other => panic!("withdrawal should be refused and not {:?}", other),
+    }
+    assert!(service
+        .get_transaction(withdrawal.client, withdrawal.transaction_id)
+        .await
+        .is_err());
+
+    let positions = service
+        .get_clients_positions()
+        .try_collect::<Vec<_>>()
+        .await
+        .expect("failed to get clients positions");
+    assert_eq!(positions[0].available, 30.into());
+}
+
+#[test]
+async fn withdrawal_chargeback_credits_client() {
+    let service = get_test_service();
+    let withdrawal = Transaction {
+        transaction_type: TransactionType::Withdrawal,
+        transaction_id: 3,
+        amount: Some(10.into()),
+        ..get_test_transaction()
+    };
+    let transactions = vec![
+        get_test_transaction(),
+        withdrawal.clone(),
+        Transaction {
+            transaction_type: TransactionType::Dispute,
+            amount: None,
+            ..withdrawal.clone()
+        },
+        Transaction {
+            transaction_type: TransactionType::Chargeback,
+            amount: None,
+            ..withdrawal
+        },
+    ];
+    let expected = ClientPosition {
+        client: 10,
+        currency: None,
+        total: 20.into(),
+        available: 20.into(),
+        held: 0.into(),
+        locked: false,
+        fees: 0.into(),
+        adjustments: 0.into(),
+        last_unlock: None,
+    };
+    let expectations = vec![
+        ClientPosition {
+            total: 30.into(),
+            available: 30.into(),
+            ..expected.clone()
+        },
+        expected.clone(),
+        ClientPosition {
+            total: 30.into(),
+            held: 10.into(),
+            ..expected.clone()
+        },
+        ClientPosition {
+            total: 30.into(),
+            available: 30.into(),
+            locked: true,
+            ..expected
+        },
+    ];
+    for (transaction, expected) in transactions.into_iter().zip(expectations) {
+        service
+            .add_transaction(transaction)
+            .await
+            .expect("failed to save transaction");
+        let positions = service
+            .get_clients_positions()
+            .try_collect::<Vec<_>>()
+            .await
+            .expect("failed to get clients positions");
+        assert_eq!(positions, vec![expected]);
+    }
+}
+
+#[test]
+async fn transfer_to_locked_account_is_refused() {
+    let service = get_test_service();
+    let locked = Transaction {
+        client: 20,
+        transaction_id: 3,
+        ..get_test_transaction()
+    };
+    let transactions = vec![
+        get_test_transaction(),
+        locked.clone(),
+        Transaction {
+            transaction_type: TransactionType::Dispute,
+            ..locked.clone()
+        },
+        Transaction {
+            transaction_type: TransactionType::Chargeback,
+            ..locked
+        },
+    ];
+    for transaction in transactions {
+        service
+            .add_transaction(transaction)
+            .await
+            .expect("failed to save transaction");
+    }
+    let transfer = Transaction {
+        transaction_type: TransactionType::Transfer,
+        transaction_id: 4,
+        destination: Some(20),
+        ..get_test_transaction()
+    };
+    match service.add_transaction(transfer).await {
+        Err(AccountLocked) => {}
+        other => panic!("transfer should be refused and not {:?}", other),
+    }
+    let positions = service
+        .get_clients_positions()
+        .try_collect::<Vec<_>>()
+        .await
+        .expect("failed to get clients positions");
+    assert_eq!(positions[0].available, 30.into());
+}
+
+#[test]
+async fn fee_within_overdraft_limit() {
+    let service = get_test_service().with_fee_overdraft_limit(5.into());
+    service
+        .add_transaction(get_test_transaction())
+        .await
+        .expect("failed to save transaction");
+    let fee = Transaction {
+        transaction_type: TransactionType::Fee,
+        transaction_id: 3,
+        amount: Some(33.into()),
+        ..get_test_transaction()
+    };
+    service
+        .add_transaction(fee.clone())
+        .await
+        .expect("fee within the limit should be charged");
+    match service
+        .add_transaction(Transaction {
+            transaction_id: 4,
+            amount: Some(3.into()),
+            ..fee
+        })
+        .await
+    {
+        Err(InsufficientFunds) => {}
+        other => panic!("fee above the limit should be refused and not {:?}", other),
+    }
+    let positions = service
+        .get_clients_positions()
+        .try_collect::<Vec<_>>()
+        .await
+        .expect("failed to get clients positions");
+    assert_eq!(positions[0].available, (-3).into());
+    assert_eq!(positions[0].fees, 33.into());
+}
+
+#[test]
+async fn unlock_locked_account() {
+    let service = get_test_service();
+    let transaction = get_test_transaction();
+    let unlock = Unlock {
+        operator: "support".to_string(),
+        reason: "customer identity verified".to_string(),
+    };
+    service
+        .add_transaction(transaction.clone())
+        .await
+        .expect("failed to save transaction");
+    match service
+        .unlock(transaction.client, None, unlock.clone())
+        .await
+    {
+        Err(AccountNotLocked) => {}
+        other => panic!("unlocked accounts cannot be unlocked, got {:?}", other),
+    }
+    for transaction_type in [TransactionType::Dispute, TransactionType::Chargeback] {
+        service
+            .add_transaction(Transaction {
+                transaction_type,
+                ..transaction.clone()
+            })
+            .await
+            .expect("failed to save transaction");
+    }
+
+    let position = service
+        .unlock(transaction.client, None, unlock.clone())
+        .await
+        .expect("failed to unlock account");
+    assert!(!position.locked);
+    assert_eq!(position.last_unlock, Some(unlock));
+    service
+        .add_transaction(Transaction {
+            transaction_id: 3,
+            ..transaction
+        })
+        .await
+        .expect("unlocked account should accept transactions");
+}
+
+#[test]
+async fn unlock_unknown_client() {
+    let service = get_test_service();
+    let unlock = Unlock {
+        operator: "support".to_string(),
+        reason: "customer identity verified".to_string(),
+    };
+    match service.unlock(5, None, unlock).await {
+        Err(ClientNotFound(5)) => {}
+        other => panic!(
+            "client without position should not be found, got {:?}",
+            other
+        ),
+    }
+}
+
+#[test]
+async fn chargeback_freezes_every_currency() {
+    let service = get_test_service();
+    let transaction = Transaction {
+        currency: Some("EUR".to_string()),
+        ..get_test_transaction()
+    };
+    for transaction_type in [
+        TransactionType::Deposit,
+        TransactionType::Dispute,
+        TransactionType::Chargeback,
+    ] {
+        service
+            .add_transaction(Transaction {
+                transaction_type,
+                ..transaction.clone()
+            })
+            .await
+            .expect("failed to save transaction");
+    }
+    let usd_deposit = Transaction {
+        transaction_id: 3,
+        currency: Some("USD".to_string()),
+        ..transaction.clone()
+    };
+    match service.add_transaction(usd_deposit.clone()).await {
+        Err(AccountLocked) => {}
+        other => panic!(
+            "locked client should refuse every currency, got {:?}",
+            other
+        ),
+    }
+
+    let unlock = Unlock {
+        operator: "support".to_string(),
+        reason: "customer identity verified".to_string(),
+    };
+    service
+        .unlock(transaction.client, transaction.currency, unlock)
+        .await
+        .expect("failed to unlock account");
+    service
+        .add_transaction(usd_deposit)
+        .await
+        .expect("unlocked client should accept every currency");
+}
+
+#[test]
+async fn transaction_history() {
+    let service = get_test_service();
+    let transaction = get_test_transaction();
+    let rows = [
+        (TransactionType::Deposit, Some(30.into())),
+        (TransactionType::Dispute, Some(10.into())),
+        (TransactionType::Resolve, None),
+        (TransactionType::Dispute, None),
+        (TransactionType::Chargeback, None),
+    ];
+    for (line, (transaction_type, amount)) in rows.into_iter().enumerate() {
+        service
+            .add_transaction(Transaction {
+                transaction_type,
+                amount,
+                line: Some(line + 2),
+                ..transaction.clone()
+            })
+            .await
+            .expect("failed to save transaction");
+    }
+
+    let record = service
+        .get_transaction(transaction.client, transaction.transaction_id)
+        .await
+        .expect("failed to get saved transaction");
+    assert_eq!(record.kind, TransactionType::Deposit);
+    assert_eq!(
+        record.transaction.transaction_type,
+        TransactionType::Chargeback
+    );
+    let history: Vec<_> = record
+        .history
+        .into_iter()
+        .map(|change| (change.state, change.amount, change.line))
+        .collect();
+    assert_eq!(
+        history,
+        vec![
+            (TransactionState::Available, 30.into(), Some(2)),
+            (TransactionState::Held, 10.into(), Some(3)),
+            (TransactionState::Available, 10.into(), Some(4)),
+            (TransactionState::Held, 30.into(), Some(5)),
+            (TransactionState::ChargedBack, 30.into(), Some(6)),
+        ]
+    );
+}
+
+#[test]
+async fn list_client_transactions() {
+    let service = get_test_service();
+    let transactions = vec![
+        get_test_transaction(),
+        Transaction {
+            client: 20,
+            transaction_id: 1,
+            ..get_test_transaction()
+        },
+        Transaction {
+            transaction_type: TransactionType::Transfer,
+            client: 20,
+            transaction_id: 5,
+            amount: Some(10.into()),
+            destination: Some(10),
+            ..get_test_transaction()
+        },
+        Transaction {
+            transaction_type: TransactionType::Withdrawal,
+            transaction_id: 3,
+            amount: Some(10.into()),
+            ..get_test_transaction()
+        },
+    ];
+    for transaction in transactions {
+        service
+            .add_transaction(transaction)
+            .await
+            .expect("failed to save transaction");
+    }
+
+    let transaction_ids: Vec<_> = service
+        .list_transactions(10)
+        .map_ok(|record| record.transaction.transaction_id)
+        .try_collect()
+        .await
+        .expect("failed to list transactions");
+    assert_eq!(transaction_ids, vec![2, 3, 5]);
+}
+
+#[test]
+async fn get_client_position() {
+    let service = get_test_service();
+    service
+        .add_transaction(get_test_transaction())
+        .await
+        .expect("failed to save transaction");
+
+    let position = service
+        .get_client_position(10, None)
+        .await
+        .expect("failed to get client position");
+    assert_eq!(position.available, 30.into());
+    assert_eq!(position.total, 30.into());
+
+    let result = service.get_client_position(20, None).await;
+    assert!(matches!(result, Err(ClientNotFound(20))));
+    let result = service.get_client_position(10, Some("EUR".into())).await;
+    assert!(matches!(result, Err(ClientNotFound(10))));
+}
+
+#[test]
+async fn add_transactions_batch() {
+    let service = get_test_service();
+    let outcomes = service
+        .add_transactions(vec![
+            get_test_transaction(),
+            Transaction {
+                transaction_type: TransactionType::Withdrawal,
+                transaction_id: 3,
+                amount: Some(40.into()),
+                ..get_test_transaction()
+            },
+            Transaction {
+                transaction_type: TransactionType::Dispute,
+                transaction_id: 9,
+                amount: None,
+                ..get_test_transaction()
+            },
+            Transaction {
+                transaction_id: 4,
+                amount: Some(20.into()),
+                ..get_test_transaction()
+            },
+            // Only covered thanks to the deposits earlier in the batch
+            Transaction {
+                transaction_type: TransactionType::Withdrawal,
+                transaction_id: 5,
+                amount: Some(45.into()),
+                ..get_test_transaction()
+            },
+        ])
+        .await;
+
+    assert_eq!(outcomes.len(), 5);
+    assert!(outcomes[0].is_ok());
+    assert!(matches!(outcomes[1], Err(InsufficientFunds)));
+    assert!(matches!(
+        outcomes[2],
+        Err(Storage(Data(TransactionNotFoundForClient(10))))
+    ));
+    assert!(outcomes[3].is_ok());
+    assert!(outcomes[4].is_ok());
+
+    assert!(matches!(
+        service.get_transaction(10, 3).await,
+        Err(Storage(Data(KeyNotFound(_))))
+    ));
+    let position = service
+        .get_client_position(10, None)
+        .await
+        .expect("failed to get client position");
+    assert_eq!(position.available, 5.into());
+    assert_eq!(position.total, 5.into());
+}
+
+#[test]
+async fn get_transaction_of_other_client() {
+    let service = get_test_service();
+    let transaction = get_test_transaction();
+    service
+        .add_transaction(transaction.clone())
+        .await
+        .expect("failed to save transaction");
+
+    let result = service
+        .get_transaction(20, transaction.transaction_id)
+        .await;
+    assert!(matches!(
+        result,
+        Err(Storage(Data(TransactionNotFoundForClient(20))))
+    ));
+}
+
+#[test]
+async fn list_client_positions() {
+    let service = get_test_service();
+    let transactions = vec![
+        get_test_transaction(),
+        Transaction {
+            transaction_id: 3,
+            currency: Some("EUR".into()),
+            ..get_test_transaction()
+        },
+        Transaction {
+            client: 1,
+            transaction_id: 4,
+            ..get_test_transaction()
+        },
+        Transaction {
+            client: 100,
+            transaction_id: 5,
+            ..get_test_transaction()
+        },
+    ];
+    for transaction in transactions {
+        service
+            .add_transaction(transaction)
+            .await
+            .expect("failed to save transaction");
+    }
+
+    let positions: Vec<_> = service
+        .list_client_positions(10)
+        .map_ok(|position| (position.client, position.currency))
+        .try_collect()
+        .await
+        .expect("failed to list client positions");
+    assert_eq!(positions, vec![(10, None), (10, Some("EUR".into()))]);
+}
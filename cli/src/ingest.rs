@@ -0,0 +1,209 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use account_service::Service;
+use color_eyre::{eyre::WrapErr, Result};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::{
+        tcp::OwnedWriteHalf,
+        {TcpListener, TcpStream},
+    },
+    sync::mpsc,
+};
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::{Cli, InputFormat, OutputFormat, ProcessingMode};
+
+/// Default for [`Ingestion::with_max_queued_rejects`].
+pub const MAX_QUEUED_REJECTS: usize = 64 * 1024 * 1024;
+
+/// Accepts producers pushing transactions and clients asking for positions, all sharing one
+/// account service.
+pub struct Ingestion {
+    service: Arc<dyn Service>,
+    input_format: InputFormat,
+    output_format: OutputFormat,
+    currency_column: bool,
+    max_queued_rejects: usize,
+}
+
+impl Ingestion {
+    pub fn new(service: Arc<dyn Service>) -> Self {
+        Self {
+            service,
+            input_format: Default::default(),
+            output_format: Default::default(),
+            currency_column: false,
+            max_queued_rejects: MAX_QUEUED_REJECTS,
+        }
+    }
+
+    /// Format producers send their transactions in.
+    pub fn with_input_format(mut self, input_format: InputFormat) -> Self {
+        self.input_format = input_format;
+        self
+    }
+
+    /// Format snapshots are printed in.
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// Prints the currency of every position in snapshots, see [`Cli::with_currency_column`].
+    pub fn with_currency_column(mut self, currency_column: bool) -> Self {
+        self.currency_column = currency_column;
+        self
+    }
+
+    /// Bytes of rejected rows kept for a producer that does not read them yet. A producer going
+    /// past it is disconnected, the rows it sent until then stay applied.
+    pub fn with_max_queued_rejects(mut self, max_queued_rejects: usize) -> Self {
+        self.max_queued_rejects = max_queued_rejects;
+        self
+    }
+
+    /// Runs until accepting a connection fails.
+    ///
+    /// Every connection to `producers` is read until its end, transactions applied in the order
+    /// they were sent and rejected rows written back to it as CSV, like
+    /// [`Cli::with_rejects`] does. Connections are served concurrently, so rows of one client are
+    /// applied in order as long as a single producer sends them. Rejects wait in memory until the
+    /// producer reads them, up to [`Ingestion::with_max_queued_rejects`].
+    ///
+    /// Every connection to `snapshots` receives the positions as they are at that moment and is
+    /// closed.
+    pub async fn run(self, producers: TcpListener, snapshots: TcpListener) -> Result<()> {
+        info!(
+            producers = %producers.local_addr()?,
+            snapshots = %snapshots.local_addr()?,
+            "Waiting for connections"
+        );
+        let this = Arc::new(self);
+        tokio::try_join!(
+            this.clone().accept(producers, Self::ingest),
+            this.accept(snapshots, Self::snapshot),
+        )?;
+        Ok(())
+    }
+
+    async fn accept<F, Fut>(self: Arc<Self>, listener: TcpListener, handle: F) -> Result<()>
+    where
+        F: Fn(Arc<Self>, TcpStream) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        loop {
+            let (stream, peer) = listener
+                .accept()
+                .await
+                .wrap_err("failed to accept connection")?;
+            let connection = handle(self.clone(), stream);
+            tokio::spawn(
+                async move {
+                    if let Err(e) = connection.await {
+                        warn!(error = ?e, "connection failed");
+                    }
+                }
+                .instrument(info_span!("connection", peer = %peer)),
+            );
+        }
+    }
+
+    async fn ingest(self: Arc<Self>, stream: TcpStream) -> Result<()> {
+        let (input, output) = stream.into_split();
+        // Writing rejects straight to the socket would block reading whenever the producer only
+        // reads once it is done sending, and the producer would block on us in turn.
+        let (rejects, queue) = mpsc::unbounded_channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let writer = tokio::spawn(Self::write_rejects(queue, queued.clone(), output));
+        let processed = Cli::with_service(self.service.clone())
+            .with_mode(ProcessingMode::Lenient)
+            .with_input_format(self.input_format)
+            .with_rejects(QueuedWriter {
+                rejects,
+                queued,
+                max_queued: self.max_queued_rejects,
+            })
+            .process_transactions(input)
+            .await;
+        if processed.is_err() {
+            // Dropping the write half along with the read one closes the connection instead of
+            // waiting for a producer that may never read what is queued.
+            writer.abort();
+            return processed;
+        }
+        // The client is gone, so is the sending end of the queue
+        writer.await?
+    }
+
+    async fn write_rejects(
+        mut queue: mpsc::UnboundedReceiver<Vec<u8>>,
+        queued: Arc<AtomicUsize>,
+        mut output: OwnedWriteHalf,
+    ) -> Result<()> {
+        while let Some(rejects) = queue.recv().await {
+            output
+                .write_all(&rejects)
+                .await
+                .wrap_err("failed to send rejected transactions")?;
+            queued.fetch_sub(rejects.len(), Ordering::Relaxed);
+        }
+        output
+            .shutdown()
+            .await
+            .wrap_err("failed to close rejected transactions")
+    }
+
+    async fn snapshot(self: Arc<Self>, stream: TcpStream) -> Result<()> {
+        Cli::with_service(self.service.clone())
+            .with_output_format(self.output_format)
+            .with_currency_column(self.currency_column)
+            .print_clients_positions(stream)
+            .await
+    }
+}
+
+/// Queues whatever is written for [`Ingestion::write_rejects`], never making the writer wait but
+/// failing once `max_queued` bytes are waiting to be sent.
+struct QueuedWriter {
+    rejects: mpsc::UnboundedSender<Vec<u8>>,
+    queued: Arc<AtomicUsize>,
+    max_queued: usize,
+}
+
+impl AsyncWrite for QueuedWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.queued.load(Ordering::Relaxed) + buf.len() > self.max_queued {
+            return Poll::Ready(Err(io::Error::other(
+                "too many rejected transactions waiting for the producer to read them",
+            )));
+        }
+        self.queued.fetch_add(buf.len(), Ordering::Relaxed);
+        let sent = self
+            .rejects
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "rejects are no longer sent"));
+        Poll::Ready(sent.map(|()| buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
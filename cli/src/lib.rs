@@ -1,12 +1,40 @@
-use std::{io::stderr, sync::Once};
+use std::{
+    io::stderr,
+    path::Path,
+    sync::{Arc, Once},
+};
 
-use color_eyre::{eyre::WrapErr, Result};
+use account_service::{Service, ServiceImpl};
+use clap::ArgEnum;
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Report, Result, Section,
+};
+use csv_async::AsyncSerializer;
 use futures_util::{pin_mut, StreamExt};
-use tokio::io::{AsyncRead, AsyncWrite};
-use tracing::{info, instrument, Level};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::Mutex,
+};
+
+use tracing::{info, instrument, warn, Level};
 use tracing_error::ErrorLayer;
 use tracing_subscriber::{fmt, prelude::*, Registry};
-use transaction::Transaction;
+use transaction::{
+    client::{Client, Currency},
+    Row, Transaction,
+};
+
+pub use crate::output::{OutputFormat, PositionsOrder, PositionsReport, MAX_SORTED_POSITIONS};
+use crate::{output::PositionsWriter, rejects::Rejection, workers::Workers};
+
+pub mod ingest;
+mod output;
+pub mod rejects;
+pub mod server;
+mod workers;
+
+type RejectsWriter = AsyncSerializer<Box<dyn AsyncWrite + Send + Sync + Unpin>>;
 
 static INSTRUMENTATION: Once = Once::new();
 
@@ -22,17 +50,133 @@ pub fn setup_instrumentation() {
     })
 }
 
+/// What to do with rows that cannot be read or that are refused by the account service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum ProcessingMode {
+    /// Stop at the first erroneous row
+    #[default]
+    Strict,
+    /// Log erroneous rows and carry on with the next ones
+    Lenient,
+}
+
+/// Encoding of the transactions given as input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum InputFormat {
+    /// Comma separated values with a header line
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+#[derive(Clone)]
 pub struct Cli {
-    account_service: Box<dyn account_service::Service>,
+    account_service: Arc<dyn Service>,
+    mode: ProcessingMode,
+    input_format: InputFormat,
+    output_format: OutputFormat,
+    positions_order: PositionsOrder,
+    positions_report: PositionsReport,
+    max_sorted_positions: usize,
+    currency_column: bool,
+    rejects: Option<Arc<Mutex<RejectsWriter>>>,
+    workers: usize,
 }
 
 impl Cli {
     #[instrument(err)]
     pub fn new() -> Result<Self> {
         setup_instrumentation();
-        Ok(Self {
-            account_service: Box::new(account_service::ServiceImpl::with_sled()?),
-        })
+        Ok(Self::with_service(Arc::new(ServiceImpl::with_sled()?)))
+    }
+
+    /// Creates a client backed by the database at `path`, so balances from previous runs are
+    /// carried over.
+    #[instrument(skip_all, fields(path = %path.as_ref().display()), err)]
+    pub fn with_database<P: AsRef<Path>>(path: P) -> Result<Self> {
+        setup_instrumentation();
+        Ok(Self::with_service(Arc::new(ServiceImpl::with_sled_at(
+            path,
+        )?)))
+    }
+
+    /// Creates a client on top of `account_service`, which may be shared with other clients.
+    pub fn with_service(account_service: Arc<dyn Service>) -> Self {
+        Self {
+            account_service,
+            mode: Default::default(),
+            input_format: Default::default(),
+            output_format: Default::default(),
+            positions_order: Default::default(),
+            positions_report: Default::default(),
+            max_sorted_positions: MAX_SORTED_POSITIONS,
+            currency_column: false,
+            rejects: None,
+            workers: 1,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: ProcessingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_input_format(mut self, input_format: InputFormat) -> Self {
+        self.input_format = input_format;
+        self
+    }
+
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    pub fn with_positions_order(mut self, positions_order: PositionsOrder) -> Self {
+        self.positions_order = positions_order;
+        self
+    }
+
+    /// Most positions printed in an order other than [`PositionsOrder::Client`], which sorts
+    /// them in memory. Printing fails past it rather than exhausting memory.
+    pub fn with_max_sorted_positions(mut self, max_sorted_positions: usize) -> Self {
+        self.max_sorted_positions = max_sorted_positions;
+        self
+    }
+
+    pub fn with_positions_report(mut self, positions_report: PositionsReport) -> Self {
+        self.positions_report = positions_report;
+        self
+    }
+
+    /// Prints the currency of every position, needed to tell apart the positions of clients
+    /// holding several currencies. Left out by default, keeping the columns of earlier versions.
+    pub fn with_currency_column(mut self, currency_column: bool) -> Self {
+        self.currency_column = currency_column;
+        self
+    }
+
+    /// Applies the rows of different clients concurrently on `workers` tasks, the rows of a
+    /// client keeping their order. Final positions are the same as with a single worker, but
+    /// rejects may be written in another order and, in strict mode, rows after the failing one
+    /// may already have been applied.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Writes every row that was not applied, and why, as CSV into `writer`.
+    pub fn with_rejects<W>(mut self, writer: W) -> Self
+    where
+        W: AsyncWrite + Send + Sync + Unpin + 'static,
+    {
+        let writer: Box<dyn AsyncWrite + Send + Sync + Unpin> = Box::new(writer);
+        let serializer = csv_async::AsyncWriterBuilder::new()
+            .delimiter(b',')
+            .has_headers(true)
+            .create_serializer(writer);
+        self.rejects = Some(Arc::new(Mutex::new(serializer)));
+        self
     }
 
     #[instrument(skip_all, err)]
@@ -50,27 +194,101 @@ impl Cli {
         Ok(())
     }
 
+    /// Applies every transaction read from `input`, see [`Cli::with_mode`] for what happens to the
+    /// ones that fail.
     #[instrument(skip_all, err)]
-    async fn process_transactions<I>(&self, input: I) -> Result<()>
+    pub async fn process_transactions<I>(&self, input: I) -> Result<()>
     where
         I: AsyncRead + Unpin + Send,
     {
-        let transactions = Transaction::from_reader(input).await.enumerate();
-        pin_mut!(transactions);
-
-        while let Some((i, transaction)) = transactions.next().await {
-            let line = i + 2;
-            let transaction = transaction
-                .wrap_err_with(|| format!("failed to read transaction on line #{}", line))?;
-            self.process_transaction(transaction)
-                .await
-                .wrap_err_with(|| format!("failed to process transaction on line #{}", line))?;
+        let rows = match self.input_format {
+            InputFormat::Csv => Transaction::from_reader(input).await.boxed(),
+            InputFormat::Jsonl => Transaction::from_json_lines_reader(input).await.boxed(),
+        };
+        pin_mut!(rows);
+
+        let mut workers = (self.workers > 1).then(|| Workers::spawn(self, self.workers));
+        while let Some(Row {
+            line,
+            raw,
+            transaction,
+        }) = rows.next().await
+        {
+            let transaction = match transaction
+                .wrap_err_with(|| format!("failed to read transaction on line #{}", line))
+            {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    self.refuse(line, &raw, None, e).await?;
+                    continue;
+                }
+            };
+            match &mut workers {
+                Some(workers) => workers.dispatch(self, line, raw, transaction).await?,
+                None => self.apply(line, &raw, transaction).await?,
+            }
+        }
+        if let Some(workers) = workers {
+            workers.join().await?;
         }
 
         info!("Processed transactions");
         Ok(())
     }
 
+    /// Applies the transaction read from `raw` on `line`, only returning why it was refused in
+    /// strict mode.
+    async fn apply(&self, line: usize, raw: &str, transaction: Transaction) -> Result<()> {
+        let result = self
+            .process_transaction(transaction.clone())
+            .await
+            .wrap_err_with(|| format!("failed to process transaction on line #{}", line));
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => self.refuse(line, raw, Some(transaction), e).await,
+        }
+    }
+
+    async fn refuse(
+        &self,
+        line: usize,
+        raw: &str,
+        transaction: Option<Transaction>,
+        error: Report,
+    ) -> Result<()> {
+        self.reject(line, raw, transaction, &error).await?;
+        match self.mode {
+            ProcessingMode::Strict => Err(error),
+            ProcessingMode::Lenient => {
+                warn!(line, error = %error.root_cause(), "skipping transaction");
+                Ok(())
+            }
+        }
+    }
+
+    async fn reject(
+        &self,
+        line: usize,
+        raw: &str,
+        transaction: Option<Transaction>,
+        error: &Report,
+    ) -> Result<()> {
+        if let Some(rejects) = &self.rejects {
+            let mut rejects = rejects.lock().await;
+            rejects
+                .serialize(Rejection::new(line, raw, transaction, error))
+                .await
+                .wrap_err("failed to write rejected transaction")?;
+            // Rejects are rare, flushing each of them keeps the file complete even when strict
+            // mode aborts right after.
+            rejects
+                .flush()
+                .await
+                .wrap_err("failed to flush rejected transactions")?;
+        }
+        Ok(())
+    }
+
     #[instrument(
         fields(
             client = transaction.client,
@@ -89,29 +307,74 @@ impl Cli {
         Ok(())
     }
 
+    /// Prints every client position into `writer` as configured by [`Cli::with_output_format`],
+    /// [`Cli::with_positions_order`], [`Cli::with_positions_report`] and
+    /// [`Cli::with_currency_column`].
     #[instrument(skip_all, err)]
-    async fn print_clients_positions<O>(&self, writer: O) -> Result<()>
+    pub async fn print_clients_positions<O>(&self, writer: O) -> Result<()>
     where
         O: AsyncWrite + Unpin + Send + Sync,
     {
-        let mut writer = csv_async::AsyncWriterBuilder::new()
-            .delimiter(b',')
-            .has_headers(true)
-            .create_serializer(writer);
+        let mut writer = PositionsWriter::new(
+            self.output_format,
+            self.positions_report,
+            self.currency_column,
+            writer,
+        );
+
+        let positions = self.account_service.get_clients_positions();
+        if self.positions_order == PositionsOrder::Client {
+            pin_mut!(positions);
+            while let Some(position) = positions.next().await {
+                let position = position.wrap_err("failed to get clients positions")?;
+                writer.write(&position).await?;
+            }
+        } else {
+            pin_mut!(positions);
+            let mut sorted = Vec::new();
+            while let Some(position) = positions.next().await {
+                if sorted.len() == self.max_sorted_positions {
+                    return Err(eyre!(
+                        "more than {} clients positions to sort in memory",
+                        self.max_sorted_positions
+                    ))
+                    .suggestion("print them by ascending client id with `--sort client`");
+                }
+                sorted.push(position.wrap_err("failed to get clients positions")?);
+            }
+            self.positions_order.sort(&mut sorted);
+            for position in sorted {
+                writer.write(&position).await?;
+            }
+        }
+        writer.finish().await
+    }
 
-        // In case of a big amount clients, this would actually have to be a stream
-        let positions = self
+    /// Prints the position of `client` in `currency` into `writer` as configured by
+    /// [`Cli::with_output_format`], [`Cli::with_positions_report`] and
+    /// [`Cli::with_currency_column`].
+    #[instrument(skip(self, writer), err)]
+    pub async fn print_client_position<O>(
+        &self,
+        client: Client,
+        currency: Option<Currency>,
+        writer: O,
+    ) -> Result<()>
+    where
+        O: AsyncWrite + Unpin + Send + Sync,
+    {
+        let position = self
             .account_service
-            .get_clients_positions()
+            .get_client_position(client, currency)
             .await
-            .wrap_err("failed to get clients positions")?;
-
-        for position in positions {
-            writer
-                .serialize(&position)
-                .await
-                .wrap_err("failed to serialize client position")?;
-        }
-        Ok(())
+            .wrap_err("failed to get client position")?;
+        let mut writer = PositionsWriter::new(
+            self.output_format,
+            self.positions_report,
+            self.currency_column,
+            writer,
+        );
+        writer.write(&position).await?;
+        writer.finish().await
     }
 }
@@ -1,26 +1,256 @@
-use std::env::args;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
-use color_eyre::{
-    eyre::{bail, WrapErr},
-    Result, Section,
+use account_service::{Service, ServiceImpl};
+use clap::{Parser, Subcommand};
+use color_eyre::{eyre::WrapErr, Result, Section};
+use krak_it::{
+    ingest::{Ingestion, MAX_QUEUED_REJECTS},
+    Cli, InputFormat, OutputFormat, PositionsOrder, PositionsReport, ProcessingMode,
 };
-use krak_it::Cli;
-use tokio::{fs::File, io::stdout};
+use rust_decimal::Decimal;
+use storage::RetryPolicy;
+use tokio::{fs::File, io::stdout, net::TcpListener};
 use tracing::info;
+use transaction::client::{Client, Currency};
+
+#[derive(Debug, Parser)]
+#[clap(
+    about,
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// File with the transactions to process
+    #[clap(required = true)]
+    input_file: Option<PathBuf>,
+
+    /// Format of the input file
+    #[clap(long, arg_enum, default_value_t)]
+    input_format: InputFormat,
+
+    /// Format used to print the clients positions
+    #[clap(long, arg_enum, default_value_t)]
+    output_format: OutputFormat,
+
+    /// Order used to print the clients positions; orders other than `client` sort them in memory
+    /// and fail past a million positions
+    #[clap(long, arg_enum, default_value_t)]
+    sort: PositionsOrder,
+
+    /// Columns printed for every client position
+    #[clap(long, arg_enum, default_value_t)]
+    report: PositionsReport,
+
+    /// Prints the currency of every position, to tell apart clients holding several currencies
+    #[clap(long)]
+    currency_column: bool,
+
+    #[clap(flatten)]
+    service: ServiceArgs,
+
+    /// How to handle rows that cannot be read or processed
+    #[clap(long, arg_enum, default_value_t)]
+    mode: ProcessingMode,
+
+    /// CSV file that receives every row that was not applied along with the reason
+    #[clap(long)]
+    rejects: Option<PathBuf>,
+
+    /// Number of tasks applying transactions, the rows of a client always go to the same one
+    #[clap(long, default_value_t = 1)]
+    workers: usize,
+}
+
+/// How the account service is set up.
+#[derive(Debug, clap::Args)]
+struct ServiceArgs {
+    /// Directory of a database that keeps positions and transactions between runs; without it
+    /// everything is kept in a temporary database
+    #[clap(long)]
+    database: Option<PathBuf>,
+
+    /// How far below zero a fee may take the available funds of a client
+    #[clap(long, default_value_t)]
+    fee_overdraft_limit: Decimal,
+
+    /// How many times a write that conflicts with another one is tried before giving up
+    #[clap(long, default_value_t = RetryPolicy::default().attempts)]
+    conflict_attempts: usize,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Serves the account service over HTTP instead of processing a file
+    Serve {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+
+        #[clap(flatten)]
+        service: ServiceArgs,
+    },
+    /// Applies transactions pushed by producers over TCP and prints the positions to whoever
+    /// connects to the snapshot address
+    Listen {
+        /// Address producers connect to
+        #[clap(long, default_value = "127.0.0.1:7070")]
+        listen: SocketAddr,
+
+        /// Address that answers every connection with the current positions
+        #[clap(long, default_value = "127.0.0.1:7071")]
+        snapshot_listen: SocketAddr,
+
+        /// Format producers send transactions in
+        #[clap(long, arg_enum, default_value_t)]
+        input_format: InputFormat,
+
+        /// Format used to print the clients positions
+        #[clap(long, arg_enum, default_value_t)]
+        output_format: OutputFormat,
+
+        /// Prints the currency of every position, to tell apart clients holding several
+        /// currencies
+        #[clap(long)]
+        currency_column: bool,
+
+        /// Bytes of rejected rows kept for a producer that does not read them yet, past which it
+        /// is disconnected
+        #[clap(long, default_value_t = MAX_QUEUED_REJECTS)]
+        max_queued_rejects: usize,
+
+        #[clap(flatten)]
+        service: ServiceArgs,
+    },
+    /// Prints the position of one client kept in a database
+    Position {
+        /// Client to look up
+        client: Client,
+
+        /// Currency of the position; without it the position without currency is printed
+        #[clap(long)]
+        currency: Option<Currency>,
+
+        /// Format used to print the client position
+        #[clap(long, arg_enum, default_value_t)]
+        output_format: OutputFormat,
+
+        /// Columns printed for the client position
+        #[clap(long, arg_enum, default_value_t)]
+        report: PositionsReport,
+
+        /// Directory of the database holding the position
+        #[clap(long)]
+        database: PathBuf,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     krak_it::setup_instrumentation();
 
-    let input_file = if let Some(input_file) = args().nth(1) {
-        input_file
-    } else {
-        bail!("Supply an input file");
+    let args = Args::parse();
+    match args.command {
+        Some(Command::Serve { listen, service }) => serve(listen, service).await,
+        Some(Command::Listen {
+            listen,
+            snapshot_listen,
+            input_format,
+            output_format,
+            currency_column,
+            max_queued_rejects,
+            service,
+        }) => {
+            let producers = bind(listen).await?;
+            let snapshots = bind(snapshot_listen).await?;
+            Ingestion::new(open_service(service)?)
+                .with_input_format(input_format)
+                .with_output_format(output_format)
+                .with_currency_column(currency_column)
+                .with_max_queued_rejects(max_queued_rejects)
+                .run(producers, snapshots)
+                .await
+        }
+        Some(Command::Position {
+            client,
+            currency,
+            output_format,
+            report,
+            database,
+        }) => {
+            let service = ServiceArgs {
+                database: Some(database),
+                fee_overdraft_limit: Decimal::ZERO,
+                conflict_attempts: RetryPolicy::default().attempts,
+            };
+            Cli::with_service(open_service(service)?)
+                .with_output_format(output_format)
+                .with_positions_report(report)
+                .with_currency_column(currency.is_some())
+                .print_client_position(client, currency, stdout())
+                .await
+        }
+        None => process_file(args).await,
+    }
+}
+
+async fn serve(listen: SocketAddr, service: ServiceArgs) -> Result<()> {
+    let listener = bind(listen).await?;
+    krak_it::server::serve(open_service(service)?, listener).await
+}
+
+fn open_service(args: ServiceArgs) -> Result<Arc<dyn Service>> {
+    let service = match &args.database {
+        Some(database) => ServiceImpl::with_sled_at(database)
+            .wrap_err("failed to open account service")
+            .with_section(|| format!("Database: {}", database.display()))?,
+        None => ServiceImpl::with_sled().wrap_err("failed to open account service")?,
     };
+    let retry_policy = RetryPolicy {
+        attempts: args.conflict_attempts.max(1),
+        ..Default::default()
+    };
+    Ok(Arc::new(
+        service
+            .with_fee_overdraft_limit(args.fee_overdraft_limit)
+            .with_retry_policy(retry_policy),
+    ))
+}
 
-    let section = || format!("Input file: {input_file}");
+async fn bind(address: SocketAddr) -> Result<TcpListener> {
+    TcpListener::bind(address)
+        .await
+        .wrap_err("failed to listen")
+        .with_section(|| format!("Address: {address}"))
+}
 
-    let client = Cli::new().wrap_err("failed to create client")?;
+async fn process_file(args: Args) -> Result<()> {
+    let input_file = args
+        .input_file
+        .expect("clap requires an input file without a subcommand");
+    let section = || format!("Input file: {}", input_file.display());
+
+    let client = Cli::with_service(open_service(args.service)?)
+        .with_mode(args.mode)
+        .with_input_format(args.input_format)
+        .with_output_format(args.output_format)
+        .with_positions_order(args.sort)
+        .with_positions_report(args.report)
+        .with_currency_column(args.currency_column)
+        .with_workers(args.workers);
+    let client = match &args.rejects {
+        Some(rejects) => {
+            let rejects = File::create(rejects)
+                .await
+                .wrap_err("failed to create rejects file")
+                .with_section(|| format!("Rejects file: {}", rejects.display()))?;
+            client.with_rejects(rejects)
+        }
+        None => client,
+    };
     let input = File::open(&input_file)
         .await
         .wrap_err("failed to open input file")
@@ -0,0 +1,343 @@
+use std::cmp::Ordering;
+
+use clap::ArgEnum;
+use color_eyre::{eyre::WrapErr, Result};
+use csv_async::AsyncSerializer;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use transaction::client::{Client, ClientPosition, Currency};
+
+/// Encoding used to print clients positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum OutputFormat {
+    /// Comma separated values with a header line
+    #[default]
+    Csv,
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    Ndjson,
+    /// Aligned columns meant to be read by humans
+    Table,
+}
+
+/// Default for [`Cli::with_max_sorted_positions`](crate::Cli::with_max_sorted_positions).
+pub const MAX_SORTED_POSITIONS: usize = 1_000_000;
+
+/// Order in which clients positions are printed.
+///
+/// Every order but [`PositionsOrder::Client`] holds all positions in memory to sort them, so
+/// printing fails when there are more than
+/// [`Cli::with_max_sorted_positions`](crate::Cli::with_max_sorted_positions).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum PositionsOrder {
+    /// Ascending client id, streamed straight from storage
+    #[default]
+    Client,
+    /// Descending total, sorted in memory
+    TotalDesc,
+    /// Descending available funds, sorted in memory
+    AvailableDesc,
+}
+
+/// Columns printed for every client position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ArgEnum)]
+pub enum PositionsReport {
+    /// Balances and whether the account is locked
+    #[default]
+    Basic,
+    /// Balances plus the fees charged and the sum of manual adjustments
+    Extended,
+}
+
+/// Position as printed by [`PositionsReport::Basic`].
+#[derive(Debug, Serialize)]
+struct BasicPosition<'a> {
+    client: Client,
+    /// `None` when currencies are not printed, see [`PositionsWriter::new`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'a Option<Currency>>,
+    #[serde(with = "rust_decimal::serde::str")]
+    total: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    available: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    held: Decimal,
+    locked: bool,
+}
+
+impl<'a> BasicPosition<'a> {
+    fn new(position: &'a ClientPosition, currencies: bool) -> Self {
+        Self {
+            client: position.client,
+            currency: currencies.then_some(&position.currency),
+            total: position.total,
+            available: position.available,
+            held: position.held,
+            locked: position.locked,
+        }
+    }
+}
+
+/// Position as printed by [`PositionsReport::Extended`].
+#[derive(Debug, Serialize)]
+struct ExtendedPosition<'a> {
+    client: Client,
+    /// `None` when currencies are not printed, see [`PositionsWriter::new`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'a Option<Currency>>,
+    #[serde(with = "rust_decimal::serde::str")]
+    total: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    available: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    held: Decimal,
+    locked: bool,
+    #[serde(with = "rust_decimal::serde::str")]
+    fees: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    adjustments: Decimal,
+}
+
+impl<'a> ExtendedPosition<'a> {
+    fn new(position: &'a ClientPosition, currencies: bool) -> Self {
+        Self {
+            client: position.client,
+            currency: currencies.then_some(&position.currency),
+            total: position.total,
+            available: position.available,
+            held: position.held,
+            locked: position.locked,
+            fees: position.fees,
+            adjustments: position.adjustments,
+        }
+    }
+}
+
+/// How a position is laid out in [`OutputFormat::Table`].
+trait TableRow {
+    fn header(currencies: bool) -> String;
+    fn row(&self) -> String;
+}
+
+/// Column of the currency in [`OutputFormat::Table`], empty when currencies are not printed.
+fn currency_cell(currency: Option<&str>) -> String {
+    currency.map_or_else(String::new, |currency| format!(" {:>8}", currency))
+}
+
+impl TableRow for BasicPosition<'_> {
+    fn header(currencies: bool) -> String {
+        format!(
+            "{:>20}{} {:>20} {:>20} {:>20} {:>6}\n",
+            "client",
+            currency_cell(currencies.then_some("currency")),
+            "available",
+            "held",
+            "total",
+            "locked"
+        )
+    }
+
+    fn row(&self) -> String {
+        format!(
+            "{:>20}{} {:>20} {:>20} {:>20} {:>6}\n",
+            self.client,
+            currency_cell(
+                self.currency
+                    .map(|currency| currency.as_deref().unwrap_or_default())
+            ),
+            self.available,
+            self.held,
+            self.total,
+            self.locked
+        )
+    }
+}
+
+impl TableRow for ExtendedPosition<'_> {
+    fn header(currencies: bool) -> String {
+        format!(
+            "{:>20}{} {:>20} {:>20} {:>20} {:>6} {:>20} {:>20}\n",
+            "client",
+            currency_cell(currencies.then_some("currency")),
+            "available",
+            "held",
+            "total",
+            "locked",
+            "fees",
+            "adjustments"
+        )
+    }
+
+    fn row(&self) -> String {
+        format!(
+            "{:>20}{} {:>20} {:>20} {:>20} {:>6} {:>20} {:>20}\n",
+            self.client,
+            currency_cell(
+                self.currency
+                    .map(|currency| currency.as_deref().unwrap_or_default())
+            ),
+            self.available,
+            self.held,
+            self.total,
+            self.locked,
+            self.fees,
+            self.adjustments
+        )
+    }
+}
+
+impl PositionsOrder {
+    /// Sorts `positions`, which must already be sorted by client, keeping clients ascending for
+    /// ties.
+    pub(crate) fn sort(&self, positions: &mut [ClientPosition]) {
+        let compare: fn(&ClientPosition, &ClientPosition) -> Ordering = match self {
+            PositionsOrder::Client => return,
+            PositionsOrder::TotalDesc => |a, b| b.total.cmp(&a.total),
+            PositionsOrder::AvailableDesc => |a, b| b.available.cmp(&a.available),
+        };
+        positions.sort_by(compare);
+    }
+}
+
+/// Writes positions one at a time in the chosen [`OutputFormat`], so they never have to be held
+/// in memory all together.
+pub(crate) struct PositionsWriter<O: AsyncWrite + Unpin + Send> {
+    report: PositionsReport,
+    currencies: bool,
+    sink: Sink<O>,
+}
+
+impl<O: AsyncWrite + Unpin + Send> PositionsWriter<O> {
+    /// Without `currencies` the currency column is left out, keeping the columns positions were
+    /// printed with before they had currencies.
+    pub(crate) fn new(
+        format: OutputFormat,
+        report: PositionsReport,
+        currencies: bool,
+        writer: O,
+    ) -> Self {
+        Self {
+            report,
+            currencies,
+            sink: Sink::new(format, currencies, writer),
+        }
+    }
+
+    pub(crate) async fn write(&mut self, position: &ClientPosition) -> Result<()> {
+        match self.report {
+            PositionsReport::Basic => {
+                let position = BasicPosition::new(position, self.currencies);
+                self.sink.write(&position).await
+            }
+            PositionsReport::Extended => {
+                let position = ExtendedPosition::new(position, self.currencies);
+                self.sink.write(&position).await
+            }
+        }
+    }
+
+    /// Writes whatever the format needs after the last position and flushes the writer.
+    pub(crate) async fn finish(self) -> Result<()> {
+        match self.report {
+            PositionsReport::Basic => self.sink.finish::<BasicPosition>().await,
+            PositionsReport::Extended => self.sink.finish::<ExtendedPosition>().await,
+        }
+    }
+}
+
+enum Sink<O: AsyncWrite + Unpin + Send> {
+    Csv(Box<AsyncSerializer<O>>),
+    Json {
+        writer: O,
+        written: usize,
+    },
+    Ndjson(O),
+    Table {
+        writer: O,
+        currencies: bool,
+        header_written: bool,
+    },
+}
+
+impl<O: AsyncWrite + Unpin + Send> Sink<O> {
+    fn new(format: OutputFormat, currencies: bool, writer: O) -> Self {
+        match format {
+            OutputFormat::Csv => Self::Csv(Box::new(
+                csv_async::AsyncWriterBuilder::new()
+                    .delimiter(b',')
+                    .has_headers(true)
+                    .create_serializer(writer),
+            )),
+            OutputFormat::Json => Self::Json { writer, written: 0 },
+            OutputFormat::Ndjson => Self::Ndjson(writer),
+            OutputFormat::Table => Self::Table {
+                writer,
+                currencies,
+                header_written: false,
+            },
+        }
+    }
+
+    async fn write<P: Serialize + TableRow>(&mut self, position: &P) -> Result<()> {
+        match self {
+            Self::Csv(serializer) => serializer
+                .serialize(position)
+                .await
+                .wrap_err("failed to serialize client position")?,
+            Self::Json { writer, written } => {
+                let separator: &[u8] = if *written == 0 { b"[" } else { b"," };
+                writer.write_all(separator).await?;
+                writer.write_all(&Self::to_json(position)?).await?;
+                *written += 1;
+            }
+            Self::Ndjson(writer) => {
+                writer.write_all(&Self::to_json(position)?).await?;
+                writer.write_all(b"\n").await?;
+            }
+            Self::Table {
+                writer,
+                currencies,
+                header_written,
+            } => {
+                if !*header_written {
+                    writer.write_all(P::header(*currencies).as_bytes()).await?;
+                    *header_written = true;
+                }
+                writer.write_all(position.row().as_bytes()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn finish<P: TableRow>(self) -> Result<()> {
+        match self {
+            Self::Csv(mut serializer) => serializer.flush().await?,
+            Self::Json {
+                mut writer,
+                written,
+            } => {
+                let end: &[u8] = if written == 0 { b"[]\n" } else { b"]\n" };
+                writer.write_all(end).await?;
+                writer.flush().await?;
+            }
+            Self::Ndjson(mut writer) => writer.flush().await?,
+            Self::Table {
+                mut writer,
+                currencies,
+                header_written,
+            } => {
+                if !header_written {
+                    writer.write_all(P::header(currencies).as_bytes()).await?;
+                }
+                writer.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn to_json<P: Serialize>(position: &P) -> Result<Vec<u8>> {
+        serde_json::to_vec(position).wrap_err("failed to serialize client position")
+    }
+}
@@ -0,0 +1,82 @@
+use color_eyre::Report;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use transaction::{
+    client::{Client, Currency},
+    Transaction, TransactionId, TransactionType,
+};
+
+/// Reason used for rows that could not even be parsed into a [`Transaction`].
+const INVALID_ROW: &str = "invalid_row";
+
+/// A row of the input that was not applied, along with why.
+#[derive(Debug, Serialize)]
+pub struct Rejection {
+    pub line: usize,
+    /// Stable code derived from the error, see [`account_service::errors::Error::code`].
+    pub reason: &'static str,
+    pub message: String,
+    #[serde(rename = "type")]
+    pub transaction_type: Option<TransactionType>,
+    pub client: Option<Client>,
+    #[serde(rename = "tx")]
+    pub transaction_id: Option<TransactionId>,
+    pub amount: Option<Decimal>,
+    pub currency: Option<Currency>,
+    pub destination: Option<Client>,
+    /// Reason given with the row, named so it is not mistaken for why the row was rejected.
+    pub transaction_reason: Option<String>,
+    pub operator: Option<String>,
+    /// The row exactly as found in the input, even when it could not be parsed.
+    pub raw: String,
+}
+
+impl Rejection {
+    /// `transaction` is `None` when the row could not be parsed.
+    pub fn new(line: usize, raw: &str, transaction: Option<Transaction>, error: &Report) -> Self {
+        let reason = reason_code(error);
+        let message = error.root_cause().to_string();
+        match transaction {
+            Some(transaction) => Self {
+                line,
+                reason,
+                message,
+                transaction_type: Some(transaction.transaction_type),
+                client: Some(transaction.client),
+                transaction_id: Some(transaction.transaction_id),
+                amount: transaction.amount,
+                currency: transaction.currency,
+                destination: transaction.destination,
+                transaction_reason: transaction.reason,
+                operator: transaction.operator,
+                raw: raw.to_string(),
+            },
+            None => Self {
+                line,
+                reason,
+                message,
+                transaction_type: None,
+                client: None,
+                transaction_id: None,
+                amount: None,
+                currency: None,
+                destination: None,
+                transaction_reason: None,
+                operator: None,
+                raw: raw.to_string(),
+            },
+        }
+    }
+}
+
+fn reason_code(error: &Report) -> &'static str {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<account_service::errors::Error>() {
+            return e.code();
+        }
+        if cause.downcast_ref::<transaction::Error>().is_some() {
+            return INVALID_ROW;
+        }
+    }
+    "unknown"
+}
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use account_service::{errors::Error, Service};
+use async_stream::stream;
+use axum::{
+    body::Body,
+    extract::{rejection::JsonRejection, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use color_eyre::{eyre::WrapErr, Result};
+use futures_util::{pin_mut, StreamExt, TryStreamExt};
+use serde::Serialize;
+use storage::errors::Data;
+use tokio::net::TcpListener;
+use tracing::{info, instrument};
+use transaction::{
+    client::{Client, ClientPosition},
+    Transaction, TransactionId, TransactionRecord,
+};
+
+type SharedService = Arc<dyn Service>;
+
+/// Routes exposing `service` over HTTP:
+///
+/// - `POST /transactions` applies the transaction in the JSON body
+/// - `GET /clients/:client/transactions/:tx` gets a transaction and its history
+/// - `GET /clients/:client/positions` gets the positions of a client, one per currency, and is
+///   not found for clients without any
+/// - `GET /positions` streams every position as JSON lines
+///
+/// Failures are answered with a JSON [`ErrorBody`].
+pub fn router(service: SharedService) -> Router {
+    Router::new()
+        .route("/transactions", post(add_transaction))
+        .route(
+            "/clients/:client/transactions/:transaction_id",
+            get(get_transaction),
+        )
+        .route("/clients/:client/positions", get(get_client_positions))
+        .route("/positions", get(get_clients_positions))
+        .with_state(service)
+}
+
+/// Serves [`router`] on `listener` until the process is stopped.
+pub async fn serve(service: SharedService, listener: TcpListener) -> Result<()> {
+    info!(address = %listener.local_addr()?, "Serving account service over HTTP");
+    axum::serve(listener, router(service))
+        .await
+        .wrap_err("failed to serve http requests")
+}
+
+/// Body of every failed response.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    /// Same as [`Error::code`].
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Failure of a request, answered as a JSON [`ErrorBody`].
+struct ApiError {
+    status: StatusCode,
+    body: ErrorBody,
+}
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        Self {
+            status: status(&error),
+            body: ErrorBody {
+                code: error.code(),
+                message: message(&error),
+            },
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            body: ErrorBody {
+                code: "invalid_body",
+                message: rejection.body_text(),
+            },
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
+
+/// Same as the error message, except for missing keys whose storage layout is none of the
+/// caller's business.
+fn message(error: &Error) -> String {
+    match error {
+        Error::Storage(storage::Error::Data(Data::KeyNotFound(_))) => "not found".into(),
+        _ => error.to_string(),
+    }
+}
+
+fn status(error: &Error) -> StatusCode {
+    match error {
+        Error::Storage(storage::Error::Data(data)) => match data {
+            Data::KeyNotFound(_) | Data::TransactionNotFoundForClient(_) => StatusCode::NOT_FOUND,
+            Data::InvalidTransition(..) | Data::Conflict(_) => StatusCode::CONFLICT,
+            Data::Sled(..) | Data::Serialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        },
+        Error::Storage(_) | Error::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+        Error::AccountLocked => StatusCode::LOCKED,
+        Error::AccountNotLocked => StatusCode::CONFLICT,
+        Error::ClientNotFound(_) => StatusCode::NOT_FOUND,
+        Error::AmountCannotBeNegative
+        | Error::AmountNotPositive
+        | Error::InsufficientFunds
+        | Error::MissingAmount
+        | Error::AmountAboveLimit(_)
+        | Error::MissingDestination
+        | Error::SelfTransfer
+        | Error::MissingReason
+        | Error::MissingOperator => StatusCode::UNPROCESSABLE_ENTITY,
+    }
+}
+
+#[instrument(skip_all)]
+async fn add_transaction(
+    State(service): State<SharedService>,
+    transaction: std::result::Result<Json<Transaction>, JsonRejection>,
+) -> std::result::Result<(StatusCode, Json<Transaction>), ApiError> {
+    let Json(transaction) = transaction?;
+    let transaction = service.add_transaction(transaction).await?;
+    Ok((StatusCode::CREATED, Json(transaction)))
+}
+
+#[instrument(skip(service))]
+async fn get_transaction(
+    State(service): State<SharedService>,
+    Path((client, transaction_id)): Path<(Client, TransactionId)>,
+) -> std::result::Result<Json<TransactionRecord>, ApiError> {
+    let record = service.get_transaction(client, transaction_id).await?;
+    Ok(Json(record))
+}
+
+#[instrument(skip(service))]
+async fn get_client_positions(
+    State(service): State<SharedService>,
+    Path(client): Path<Client>,
+) -> std::result::Result<Json<Vec<ClientPosition>>, ApiError> {
+    let positions: Vec<_> = service.list_client_positions(client).try_collect().await?;
+    if positions.is_empty() {
+        return Err(Error::ClientNotFound(client).into());
+    }
+    Ok(Json(positions))
+}
+
+#[instrument(skip_all)]
+async fn get_clients_positions(State(service): State<SharedService>) -> Response {
+    let lines = stream! {
+        let positions = service.get_clients_positions();
+        pin_mut!(positions);
+        while let Some(position) = positions.next().await {
+            // Failing half way can only be told by cutting the response short
+            let mut line = serde_json::to_vec(&position?)?;
+            line.push(b'\n');
+            yield Ok::<_, color_eyre::Report>(line);
+        }
+    };
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
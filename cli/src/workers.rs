@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use color_eyre::{eyre::eyre, Report, Result};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use transaction::{Transaction, TransactionId, TransactionType};
+
+use crate::Cli;
+
+/// Number of rows waiting for a worker before the reader is slowed down.
+const QUEUE_SIZE: usize = 1024;
+
+enum Job {
+    Apply {
+        line: usize,
+        raw: String,
+        transaction: Transaction,
+    },
+    /// Answered once every job queued before it has been applied.
+    Barrier(oneshot::Sender<()>),
+}
+
+/// Applies transactions on several tasks, the rows of a client always going to the same task so
+/// they keep their order.
+///
+/// Rows that move funds between two clients, or that open a transaction id already opened on
+/// another worker, are applied by the caller once every worker is idle, which makes the final
+/// positions the same as when processing the rows one at a time.
+pub(crate) struct Workers {
+    senders: Vec<mpsc::Sender<Job>>,
+    handles: Vec<JoinHandle<Result<()>>>,
+    /// Worker of the first row opening each transaction id since workers were last idle.
+    /// Transaction ids are shared by every client, so only that row may open it. Workers are
+    /// waited for once it holds as many ids as their queues, which keeps it bounded.
+    claims: HashMap<TransactionId, usize>,
+}
+
+impl Workers {
+    pub(crate) fn spawn(cli: &Cli, count: usize) -> Self {
+        let (senders, handles) = (0..count)
+            .map(|_| {
+                let (sender, mut receiver) = mpsc::channel(QUEUE_SIZE);
+                let cli = cli.clone();
+                let handle = tokio::spawn(async move {
+                    while let Some(job) = receiver.recv().await {
+                        match job {
+                            Job::Apply {
+                                line,
+                                raw,
+                                transaction,
+                            } => cli.apply(line, &raw, transaction).await?,
+                            Job::Barrier(done) => {
+                                let _ = done.send(());
+                            }
+                        }
+                    }
+                    Ok(())
+                });
+                (sender, handle)
+            })
+            .unzip();
+        Self {
+            senders,
+            handles,
+            claims: HashMap::new(),
+        }
+    }
+
+    /// Queues `transaction` on the worker of its client, or applies it right away when it also
+    /// changes the position of another client or opens a transaction id claimed by another
+    /// worker.
+    pub(crate) async fn dispatch(
+        &mut self,
+        cli: &Cli,
+        line: usize,
+        raw: String,
+        transaction: Transaction,
+    ) -> Result<()> {
+        let worker = (transaction.client % self.senders.len() as u64) as usize;
+        if self.claims.len() >= QUEUE_SIZE * self.senders.len() {
+            self.wait_idle().await?;
+        }
+        if self.claimed_elsewhere(&transaction, worker)
+            || self.spans_clients(cli, &transaction).await
+        {
+            self.wait_idle().await?;
+            return cli.apply(line, &raw, transaction).await;
+        }
+        if self.senders[worker]
+            .send(Job::Apply {
+                line,
+                raw,
+                transaction,
+            })
+            .await
+            .is_err()
+        {
+            return Err(self.failure(worker).await);
+        }
+        Ok(())
+    }
+
+    /// Waits for every queued row to be applied.
+    pub(crate) async fn join(mut self) -> Result<()> {
+        self.senders.clear();
+        for handle in std::mem::take(&mut self.handles) {
+            handle.await??;
+        }
+        Ok(())
+    }
+
+    async fn wait_idle(&mut self) -> Result<()> {
+        for worker in 0..self.senders.len() {
+            let (done, idle) = oneshot::channel();
+            if self.senders[worker].send(Job::Barrier(done)).await.is_err() || idle.await.is_err() {
+                return Err(self.failure(worker).await);
+            }
+        }
+        // Everything queued so far is stored, later rows will see which ids are taken
+        self.claims.clear();
+        Ok(())
+    }
+
+    /// Whether `transaction` opens a transaction id that a row queued on another worker opens
+    /// too, claiming the id for `worker` otherwise.
+    fn claimed_elsewhere(&mut self, transaction: &Transaction, worker: usize) -> bool {
+        let opens = matches!(
+            transaction.transaction_type,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Transfer
+                | TransactionType::Fee
+                | TransactionType::Adjustment
+        );
+        opens
+            && *self
+                .claims
+                .entry(transaction.transaction_id)
+                .or_insert(worker)
+                != worker
+    }
+
+    /// Workers only stop early on a row refused in strict mode, returns why.
+    async fn failure(&mut self, worker: usize) -> Report {
+        match (&mut self.handles[worker]).await {
+            Ok(Err(e)) => e,
+            Ok(Ok(())) => eyre!("worker stopped unexpectedly"),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// Whether applying `transaction` may change the position of a client other than its own.
+    async fn spans_clients(&self, cli: &Cli, transaction: &Transaction) -> bool {
+        match transaction.transaction_type {
+            TransactionType::Transfer => true,
+            // Claims are cleared whenever a transfer is applied, so an id claimed since then was
+            // opened by any other kind of row and needs no lookup.
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+                if self.claims.contains_key(&transaction.transaction_id) =>
+            {
+                false
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                // Transfers are applied inline, so one referenced here is already stored.
+                cli.account_service
+                    .get_transaction(transaction.client, transaction.transaction_id)
+                    .await
+                    .is_ok_and(|record| record.kind == TransactionType::Transfer)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            handle.abort();
+        }
+    }
+}
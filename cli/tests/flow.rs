@@ -1,6 +1,6 @@
 use color_eyre::{eyre::WrapErr, Result};
 use csv_async::Trim;
-use krak_it::{setup_instrumentation, Cli};
+use krak_it::{setup_instrumentation, Cli, InputFormat, ProcessingMode};
 use tokio::{fs::File, io::BufWriter, test};
 use tokio_stream::StreamExt;
 use transaction::client::ClientPosition;
@@ -12,7 +12,8 @@ macro_rules! positive_test_cases {
             let input_filename = format!("../fixtures/{}.csv", stringify!($input_file));
             let output_filename = format!("../fixtures/{}-output.csv", stringify!($input_file));
             setup_instrumentation();
-            test_case_impl(&input_filename, &output_filename).await.unwrap();
+            let client = Cli::new().expect("should create client");
+            test_case_impl(client, &input_filename, &output_filename).await.unwrap();
         }
     };
     ($input_file:ident, $($input_files:ident),+) => {
@@ -26,7 +27,14 @@ positive_test_cases!(
     chargeback,
     open_dispute,
     resolve_dispute,
-    shifted_columns
+    shifted_columns,
+    large_identifiers,
+    withdrawal_chargeback,
+    withdrawal_resolve,
+    partial_dispute,
+    transfer,
+    fees_and_adjustments,
+    unlock
 );
 
 macro_rules! negative_test_cases {
@@ -36,7 +44,8 @@ macro_rules! negative_test_cases {
             let input_filename = format!("../fixtures/{}.csv", stringify!($input_file));
             let output_filename = format!("../fixtures/{}-output.csv", stringify!($input_file));
             setup_instrumentation();
-            let err = test_case_impl(&input_filename, &output_filename).await.unwrap_err();
+            let client = Cli::new().expect("should create client");
+            let err = test_case_impl(client, &input_filename, &output_filename).await.unwrap_err();
             let expected_error = tokio::fs::read_to_string(output_filename).await.unwrap();
             assert_eq!(format!("{}", err.root_cause()), expected_error.trim());
         }
@@ -52,11 +61,98 @@ negative_test_cases!(
     invalid_transaction_type,
     resolve_different_account,
     duplicate_transaction_id,
-    negative_withdraw
+    negative_withdraw,
+    insufficient_funds,
+    dispute_above_amount,
+    transfer_insufficient_funds,
+    adjustment_without_reason,
+    locked_client_other_currency,
+    zero_amount_dispute
 );
 
-async fn test_case_impl(input_filename: &str, output_filename: &str) -> Result<()> {
-    let client = Cli::new().expect("should create client");
+#[test]
+async fn multi_currency() {
+    setup_instrumentation();
+    let client = Cli::new()
+        .expect("should create client")
+        .with_currency_column(true);
+    test_case_impl(
+        client,
+        "../fixtures/multi_currency.csv",
+        "../fixtures/multi_currency-output.csv",
+    )
+    .await
+    .unwrap();
+}
+
+#[test]
+async fn lenient() {
+    setup_instrumentation();
+    let rejects = tempfile::NamedTempFile::new().expect("failed to create rejects file");
+    let client = Cli::new()
+        .expect("should create client")
+        .with_mode(ProcessingMode::Lenient)
+        .with_rejects(File::create(rejects.path()).await.unwrap());
+    test_case_impl(
+        client,
+        "../fixtures/lenient.csv",
+        "../fixtures/lenient-output.csv",
+    )
+    .await
+    .unwrap();
+
+    let rejects = tokio::fs::read_to_string(rejects.path()).await.unwrap();
+    let expected_rejects = tokio::fs::read_to_string("../fixtures/lenient-rejects.csv")
+        .await
+        .unwrap();
+    assert_eq!(rejects, expected_rejects);
+}
+
+#[test]
+async fn json_lines() {
+    setup_instrumentation();
+    let client = Cli::new()
+        .expect("should create client")
+        .with_input_format(InputFormat::Jsonl);
+    test_case_impl(
+        client,
+        "../fixtures/json_lines.jsonl",
+        "../fixtures/json_lines-output.csv",
+    )
+    .await
+    .unwrap();
+}
+
+#[test]
+async fn json_lines_rejects_keep_line_numbers() {
+    setup_instrumentation();
+    let rejects = tempfile::NamedTempFile::new().expect("failed to create rejects file");
+    let client = Cli::new()
+        .expect("should create client")
+        .with_input_format(InputFormat::Jsonl)
+        .with_mode(ProcessingMode::Lenient)
+        .with_rejects(File::create(rejects.path()).await.unwrap());
+    let input = concat!(
+        r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1"}"#,
+        "\n\n\n",
+        r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "5"}"#,
+        "\n",
+    );
+    client
+        .process_transactions(input.as_bytes())
+        .await
+        .expect("lenient mode should skip the withdrawal");
+
+    let rejects = tokio::fs::read_to_string(rejects.path()).await.unwrap();
+    let lines: Vec<_> = rejects
+        .lines()
+        .skip(1)
+        .map(|reject| reject.split(',').next().unwrap())
+        .collect();
+    assert_eq!(lines, vec!["4"]);
+}
+
+async fn test_case_impl(client: Cli, input_filename: &str, output_filename: &str) -> Result<()> {
     let input_file = File::open(input_filename)
         .await
         .wrap_err_with(|| format!("failed to open input fixture {}", input_filename))?;
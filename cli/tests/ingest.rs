@@ -0,0 +1,127 @@
+use std::{sync::Arc, time::Duration};
+
+use account_service::ServiceImpl;
+use futures_util::future::join_all;
+use krak_it::{ingest::Ingestion, setup_instrumentation, OutputFormat};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    test,
+};
+use transaction::client::ClientPosition;
+
+#[test]
+async fn concurrent_producers() {
+    setup_instrumentation();
+    let producers = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let snapshots = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let producers_address = producers.local_addr().unwrap();
+    let snapshots_address = snapshots.local_addr().unwrap();
+    let ingestion =
+        Ingestion::new(Arc::new(ServiceImpl::in_memory())).with_output_format(OutputFormat::Ndjson);
+    tokio::spawn(ingestion.run(producers, snapshots));
+
+    // Every producer sends its own client, including a withdrawal that only succeeds when rows
+    // are applied in order and one that must be rejected.
+    let producers = (1..=8u64).map(|client| async move {
+        let mut input = String::from("type,client,tx,amount\n");
+        for i in 0..50u64 {
+            input.push_str(&format!("deposit,{client},{},1.0\n", client * 1000 + i));
+        }
+        input.push_str(&format!("withdrawal,{client},{},50\n", client * 1000 + 50));
+        input.push_str(&format!("withdrawal,{client},{},1\n", client * 1000 + 51));
+        let mut stream = TcpStream::connect(producers_address).await.unwrap();
+        stream.write_all(input.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut rejects = String::new();
+        stream.read_to_string(&mut rejects).await.unwrap();
+        rejects
+    });
+    for rejects in join_all(producers).await {
+        let lines: Vec<_> = rejects.lines().collect();
+        assert_eq!(lines.len(), 2, "unexpected rejects {:?}", lines);
+        assert!(lines[1].starts_with("53,insufficient_funds,"));
+    }
+
+    let mut snapshot = String::new();
+    TcpStream::connect(snapshots_address)
+        .await
+        .unwrap()
+        .read_to_string(&mut snapshot)
+        .await
+        .unwrap();
+    let positions: Vec<ClientPosition> = snapshot
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(positions.len(), 8);
+    for (client, position) in (1..=8).zip(positions) {
+        assert_eq!(position.client, client);
+        assert_eq!(position.available, 0.into());
+    }
+}
+
+#[test]
+async fn producer_reading_rejects_last() {
+    setup_instrumentation();
+    let producers = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let snapshots = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let producers_address = producers.local_addr().unwrap();
+    tokio::spawn(Ingestion::new(Arc::new(ServiceImpl::in_memory())).run(producers, snapshots));
+
+    // Far more rejects than socket buffers hold, sent before reading any of them, yet fewer than
+    // the default limit of queued rejects
+    let rows = 2_000;
+    let reason = "x".repeat(8 * 1024);
+    let mut input = String::from("type,client,tx,amount,reason\n");
+    for tx in 1..=rows {
+        input.push_str(&format!("withdrawal,1,{tx},1.0,{reason}\n"));
+    }
+    let producer = async {
+        let mut stream = TcpStream::connect(producers_address).await.unwrap();
+        stream.write_all(input.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut rejects = String::new();
+        stream.read_to_string(&mut rejects).await.unwrap();
+        rejects
+    };
+    let rejects = tokio::time::timeout(Duration::from_secs(60), producer)
+        .await
+        .expect("producer and ingestion should not wait on each other");
+    assert_eq!(rejects.lines().count(), rows + 1);
+}
+
+#[test]
+async fn producer_never_reading_rejects() {
+    setup_instrumentation();
+    let producers = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let snapshots = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let producers_address = producers.local_addr().unwrap();
+    tokio::spawn(
+        Ingestion::new(Arc::new(ServiceImpl::in_memory()))
+            .with_max_queued_rejects(64 * 1024)
+            .run(producers, snapshots),
+    );
+
+    let rows = 2_000;
+    let reason = "x".repeat(8 * 1024);
+    let mut input = String::from("type,client,tx,amount,reason\n");
+    for tx in 1..=rows {
+        input.push_str(&format!("withdrawal,1,{tx},1.0,{reason}\n"));
+    }
+    let producer = async {
+        let mut stream = TcpStream::connect(producers_address).await.unwrap();
+        stream.write_all(input.as_bytes()).await?;
+        stream.shutdown().await?;
+        let mut rejects = String::new();
+        stream.read_to_string(&mut rejects).await?;
+        Ok::<_, std::io::Error>(rejects)
+    };
+    let rejects = tokio::time::timeout(Duration::from_secs(60), producer)
+        .await
+        .expect("a producer past the limit should be disconnected");
+    // Depending on timing the connection is reset while sending or while reading
+    if let Ok(rejects) = rejects {
+        assert!(rejects.lines().count() < rows + 1);
+    }
+}
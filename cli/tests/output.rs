@@ -0,0 +1,218 @@
+use krak_it::{setup_instrumentation, Cli, OutputFormat, PositionsOrder, PositionsReport};
+use rust_decimal::Decimal;
+use tokio::{fs::File, test};
+use transaction::client::ClientPosition;
+
+async fn print_chargeback_fixture(output_format: OutputFormat) -> String {
+    setup_instrumentation();
+    let client = Cli::new()
+        .expect("should create client")
+        .with_output_format(output_format);
+    let input_file = File::open("../fixtures/chargeback.csv")
+        .await
+        .expect("failed to open input fixture");
+    let mut output = vec![];
+    client
+        .process_and_print_transactions(input_file, &mut output)
+        .await
+        .expect("failed to process fixture");
+    String::from_utf8(output).expect("output should be utf-8")
+}
+
+fn expected_positions() -> Vec<ClientPosition> {
+    let half: Decimal = "0.5".parse().unwrap();
+    vec![
+        ClientPosition {
+            client: 1,
+            currency: None,
+            total: half,
+            available: half,
+            held: 0.into(),
+            locked: true,
+            fees: 0.into(),
+            adjustments: 0.into(),
+            last_unlock: None,
+        },
+        ClientPosition {
+            client: 2,
+            currency: None,
+            total: 2.into(),
+            available: 2.into(),
+            held: 0.into(),
+            locked: false,
+            fees: 0.into(),
+            adjustments: 0.into(),
+            last_unlock: None,
+        },
+    ]
+}
+
+#[test]
+async fn json() {
+    let output = print_chargeback_fixture(OutputFormat::Json).await;
+    let positions: Vec<ClientPosition> =
+        serde_json::from_str(&output).expect("output should be a json array");
+    assert_eq!(positions, expected_positions());
+}
+
+#[test]
+async fn ndjson() {
+    let output = print_chargeback_fixture(OutputFormat::Ndjson).await;
+    let positions: Vec<ClientPosition> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("every line should be a json object"))
+        .collect();
+    assert_eq!(positions, expected_positions());
+}
+
+#[test]
+async fn table() {
+    let output = print_chargeback_fixture(OutputFormat::Table).await;
+    let expected = concat!(
+        "              client            available                 held                total locked\n",
+        "                   1                  0.5                    0                  0.5   true\n",
+        "                   2                    2                    0                    2  false\n",
+    );
+    assert_eq!(output, expected);
+}
+
+#[test]
+async fn table_with_currency_column() {
+    setup_instrumentation();
+    let client = Cli::new()
+        .expect("should create client")
+        .with_output_format(OutputFormat::Table)
+        .with_currency_column(true);
+    let input_file = File::open("../fixtures/multi_currency.csv")
+        .await
+        .expect("failed to open input fixture");
+    let mut output = vec![];
+    client
+        .process_and_print_transactions(input_file, &mut output)
+        .await
+        .expect("failed to process fixture");
+    let expected = concat!(
+        "              client currency            available                 held                total locked\n",
+        "                   1      EUR                  2.5                    0                  2.5  false\n",
+        "                   1      USD                    0                   10                   10  false\n",
+        "                   2                             1                    0                    1  false\n",
+        "                   2      EUR                    0                    0                    0   true\n",
+    );
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+async fn table_with_large_identifiers() {
+    setup_instrumentation();
+    let client = Cli::new()
+        .expect("should create client")
+        .with_output_format(OutputFormat::Table);
+    let input_file = File::open("../fixtures/large_identifiers.csv")
+        .await
+        .expect("failed to open input fixture");
+    let mut output = vec![];
+    client
+        .process_and_print_transactions(input_file, &mut output)
+        .await
+        .expect("failed to process fixture");
+    let output = String::from_utf8(output).unwrap();
+    let widths: Vec<_> = output.lines().map(str::len).collect();
+    assert_eq!(widths.len(), 3);
+    assert!(widths.iter().all(|width| *width == widths[0]), "{}", output);
+}
+
+#[test]
+async fn sorted_by_total_descending() {
+    setup_instrumentation();
+    let client = Cli::new()
+        .expect("should create client")
+        .with_output_format(OutputFormat::Ndjson)
+        .with_positions_order(PositionsOrder::TotalDesc);
+    let input_file = File::open("../fixtures/chargeback.csv")
+        .await
+        .expect("failed to open input fixture");
+    let mut output = vec![];
+    client
+        .process_and_print_transactions(input_file, &mut output)
+        .await
+        .expect("failed to process fixture");
+    let clients: Vec<_> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<ClientPosition>(line).unwrap().client)
+        .collect();
+    assert_eq!(clients, vec![2, 1]);
+}
+
+#[test]
+async fn extended_report() {
+    setup_instrumentation();
+    let client = Cli::new()
+        .expect("should create client")
+        .with_output_format(OutputFormat::Csv)
+        .with_positions_report(PositionsReport::Extended);
+    let input_file = File::open("../fixtures/fees_and_adjustments.csv")
+        .await
+        .expect("failed to open input fixture");
+    let mut output = vec![];
+    client
+        .process_and_print_transactions(input_file, &mut output)
+        .await
+        .expect("failed to process fixture");
+    let expected = concat!(
+        "client,total,available,held,locked,fees,adjustments\n",
+        "1,6.5,6.5,0,false,1.5,-2\n",
+        "2,2.5,2.5,0,false,0.5,3\n",
+    );
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+async fn single_client_position() {
+    setup_instrumentation();
+    let database = tempfile::tempdir().expect("failed to create database directory");
+    let input_file = File::open("../fixtures/chargeback.csv")
+        .await
+        .expect("failed to open input fixture");
+    let client = Cli::with_database(database.path()).expect("should create client");
+    client
+        .process_transactions(input_file)
+        .await
+        .expect("failed to process fixture");
+
+    let mut output = vec![];
+    client
+        .print_client_position(2, None, &mut output)
+        .await
+        .expect("failed to print client position");
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,total,available,held,locked\n2,2,2,0,false\n"
+    );
+
+    let err = client
+        .print_client_position(3, None, vec![])
+        .await
+        .unwrap_err();
+    assert_eq!(format!("{}", err.root_cause()), "client 3 has no position");
+}
+
+#[test]
+async fn too_many_positions_to_sort() {
+    setup_instrumentation();
+    let client = Cli::new()
+        .expect("should create client")
+        .with_positions_order(PositionsOrder::AvailableDesc)
+        .with_max_sorted_positions(1);
+    let input_file = File::open("../fixtures/chargeback.csv")
+        .await
+        .expect("failed to open input fixture");
+    let error = client
+        .process_and_print_transactions(input_file, vec![])
+        .await
+        .expect_err("two positions should not be sorted");
+    assert_eq!(
+        error.root_cause().to_string(),
+        "more than 1 clients positions to sort in memory"
+    );
+}
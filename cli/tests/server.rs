@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use account_service::ServiceImpl;
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use krak_it::server::router;
+use serde_json::{json, Value};
+use tokio::test;
+use tower::ServiceExt;
+use transaction::client::ClientPosition;
+
+fn test_router() -> Router {
+    router(Arc::new(ServiceImpl::in_memory()))
+}
+
+async fn send(
+    router: &Router,
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Vec<u8>) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json");
+    let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+    let response = router
+        .clone()
+        .oneshot(request.body(body).unwrap())
+        .await
+        .expect("router should answer every request");
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("failed to read response body");
+    (status, body.to_vec())
+}
+
+#[test]
+async fn transactions_and_positions() {
+    let router = test_router();
+    let deposit = json!({"type": "deposit", "client": 1, "tx": 1, "amount": "3.5"});
+    let (status, _) = send(&router, Method::POST, "/transactions", Some(deposit)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let deposit = json!({"type": "deposit", "client": 2, "tx": 2, "amount": "1"});
+    let (status, _) = send(&router, Method::POST, "/transactions", Some(deposit)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, body) = send(&router, Method::GET, "/clients/1/transactions/1", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let record: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(record["kind"], "deposit");
+
+    let (status, body) = send(&router, Method::GET, "/clients/2/positions", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let positions: Vec<ClientPosition> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(positions.len(), 1);
+    assert_eq!(positions[0].available, 1.into());
+
+    let (status, body) = send(&router, Method::GET, "/positions", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let clients: Vec<_> = String::from_utf8(body)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<ClientPosition>(line).unwrap().client)
+        .collect();
+    assert_eq!(clients, vec![1, 2]);
+}
+
+#[test]
+async fn errors_are_json() {
+    let router = test_router();
+    let rows = [
+        json!({"type": "deposit", "client": 1, "tx": 1, "amount": "3"}),
+        json!({"type": "dispute", "client": 1, "tx": 1}),
+        json!({"type": "chargeback", "client": 1, "tx": 1}),
+    ];
+    for row in rows {
+        let (status, _) = send(&router, Method::POST, "/transactions", Some(row)).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    let cases = [
+        (
+            json!({"type": "deposit", "client": 1, "tx": 2, "amount": "3"}),
+            StatusCode::LOCKED,
+            "account_locked",
+        ),
+        (
+            json!({"type": "deposit", "client": 2, "tx": 1, "amount": "3"}),
+            StatusCode::NOT_FOUND,
+            "transaction_not_found_for_client",
+        ),
+        (
+            json!({"type": "withdrawal", "client": 2, "tx": 3, "amount": "3"}),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "insufficient_funds",
+        ),
+        (
+            json!({"type": "nope", "client": 2, "tx": 4}),
+            StatusCode::BAD_REQUEST,
+            "invalid_body",
+        ),
+    ];
+    for (row, expected_status, expected_code) in cases {
+        let (status, body) = send(&router, Method::POST, "/transactions", Some(row)).await;
+        assert_eq!(status, expected_status);
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["code"], expected_code);
+    }
+
+    let (status, body) = send(&router, Method::GET, "/clients/1/transactions/9", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let error: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["code"], "key_not_found");
+    assert_eq!(error["message"], "not found");
+
+    let (status, body) = send(&router, Method::GET, "/clients/7/positions", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let error: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["code"], "client_not_found");
+}
+
+#[test]
+async fn transactions_of_other_clients_are_not_found() {
+    let router = test_router();
+    let rows = [
+        json!({"type": "deposit", "client": 1, "tx": 1, "amount": "3"}),
+        json!({"type": "transfer", "client": 1, "tx": 2, "amount": "1", "destination": 3}),
+    ];
+    for row in rows {
+        let (status, _) = send(&router, Method::POST, "/transactions", Some(row)).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    let (status, body) = send(&router, Method::GET, "/clients/2/transactions/1", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let error: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["code"], "transaction_not_found_for_client");
+
+    // The destination of a transfer sees it as well
+    let (status, _) = send(&router, Method::GET, "/clients/3/transactions/2", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
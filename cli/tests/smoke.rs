@@ -14,3 +14,25 @@ async fn smoke() {
         exit.code()
     )
 }
+
+#[test]
+async fn fee_overdraft_limit() {
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--")
+        .arg("--fee-overdraft-limit")
+        .arg("1")
+        .arg("../fixtures/fee_overdraft.csv")
+        .output()
+        .await
+        .expect("failed to run cargo");
+    assert!(
+        output.status.success(),
+        "cargo run did not succeed exit code {:?}",
+        output.status.code()
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,available,held,locked\n1,-0.5,-0.5,0,false\n"
+    );
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use account_service::ServiceImpl;
+use krak_it::{setup_instrumentation, Cli, ProcessingMode};
+use tokio::test;
+
+/// Rows for a handful of clients mixing every kind of transaction, some of which are refused.
+fn generated_input() -> String {
+    let mut input = String::from("type,client,tx,amount,destination,reason\n");
+    let mut seed: u64 = 42;
+    let mut next = |bound: u64| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) % bound
+    };
+    for tx in 1..=1000u64 {
+        let client = next(16) + 1;
+        let amount = format!("{}.{:02}", next(50), next(100));
+        let referenced = next(tx) + 1;
+        // Transaction ids are shared by every client, some rows reuse one opened by someone else
+        let tx = if next(20) == 0 { referenced } else { tx };
+        let row = match next(10) {
+            0..=2 => format!("deposit,{},{},{},,", client, tx, amount),
+            3 | 4 => format!("withdrawal,{},{},{},,", client, tx, amount),
+            5 => format!("transfer,{},{},{},{},", client, tx, amount, next(16) + 1),
+            6 => format!("fee,{},{},{},,", client, tx, amount),
+            7 => format!("dispute,{},{},,,", client, referenced),
+            8 => format!("resolve,{},{},,,", client, referenced),
+            _ => format!("chargeback,{},{},,,", client, referenced),
+        };
+        input.push_str(&row);
+        input.push('\n');
+    }
+    input
+}
+
+async fn positions(input: &str, workers: usize) -> String {
+    setup_instrumentation();
+    let client = Cli::with_service(Arc::new(ServiceImpl::in_memory()))
+        .with_mode(ProcessingMode::Lenient)
+        .with_workers(workers);
+    let mut output = vec![];
+    client
+        .process_and_print_transactions(input.as_bytes(), &mut output)
+        .await
+        .expect("failed to process input");
+    String::from_utf8(output).expect("output should be utf-8")
+}
+
+#[test(flavor = "multi_thread", worker_threads = 4)]
+async fn parallel_matches_sequential() {
+    let input = generated_input();
+    let sequential = positions(&input, 1).await;
+    assert_eq!(sequential.lines().count(), 17);
+    assert_eq!(positions(&input, 4).await, sequential);
+}
+
+#[test(flavor = "multi_thread", worker_threads = 4)]
+async fn colliding_transaction_ids() {
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=2000 {
+        input.push_str(&format!("deposit,1,{},1.0\n", tx));
+    }
+    // Only the first row of the input may open a transaction id, whichever worker gets it first
+    input.push_str("deposit,1,90000,7.0\ndeposit,2,90000,3.0\n");
+
+    let sequential = positions(&input, 1).await;
+    assert_eq!(
+        sequential,
+        "client,total,available,held,locked\n1,2007,2007,0,false\n"
+    );
+    assert_eq!(positions(&input, 4).await, sequential);
+}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+use transaction::client::Client;
+
+use crate::implement_storage;
+
+/// Whether a chargeback froze a client. Positions are stored per currency, so this is what rows
+/// in any currency check before touching them.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ClientLock {
+    pub client: Client,
+    pub locked: bool,
+}
+
+implement_storage!(
+    ClientLock,
+    |this: &ClientLock| format!("client-lock-{:020}", this.client),
+    |this: &ClientLock| this.client
+);
@@ -1,9 +1,19 @@
-use transaction::client::ClientPosition;
+use transaction::client::{Client, ClientPosition};
 
 use crate::implement_storage;
 
+/// Prefix shared by the keys of every position of `client`, see [`crate::Storage::list`].
+pub fn client_position_prefix(client: Client) -> String {
+    // Zero padded so listing positions by key sorts them by client and then by currency
+    format!("client-position-{:020}-", client)
+}
+
 implement_storage!(
     ClientPosition,
-    |this: &ClientPosition| format!("client-position-{}", this.client),
+    |this: &ClientPosition| format!(
+        "{}{}",
+        client_position_prefix(this.client),
+        this.currency.as_deref().unwrap_or_default()
+    ),
     |this: &ClientPosition| this.client
 );
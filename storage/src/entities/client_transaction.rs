@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+use transaction::{client::Client, TransactionId};
+
+use crate::implement_storage;
+
+/// Index entry from a client to a transaction it opened or received, so a client's transactions
+/// can be listed without going through everyone else's.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ClientTransaction {
+    pub client: Client,
+    pub transaction_id: TransactionId,
+}
+
+impl ClientTransaction {
+    /// Prefix shared by the keys of every entry of `client`, see [`crate::Storage::list`].
+    pub fn client_prefix(client: Client) -> String {
+        // Zero padded so listing them sorts them by transaction id
+        format!("client-transaction-{:020}-", client)
+    }
+}
+
+implement_storage!(
+    ClientTransaction,
+    |this: &ClientTransaction| format!(
+        "{}{:020}",
+        ClientTransaction::client_prefix(this.client),
+        this.transaction_id
+    ),
+    |this: &ClientTransaction| this.client
+);
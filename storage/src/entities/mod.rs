@@ -1,6 +1,12 @@
+mod client_lock;
 mod client_position;
+mod client_transaction;
 mod transaction;
 
+pub use client_lock::ClientLock;
+pub use client_position::client_position_prefix;
+pub use client_transaction::ClientTransaction;
+
 #[macro_export]
 macro_rules! implement_storage {
     ($type_name:ident, $primary_key:expr, $partition_key:expr) => {
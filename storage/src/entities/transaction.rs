@@ -1,9 +1,9 @@
-use transaction::Transaction;
+use transaction::TransactionRecord;
 
 use crate::implement_storage;
 
 implement_storage!(
-    Transaction,
-    |this: &Transaction| format!("transaction-{}", this.transaction_id),
-    |this: &Transaction| this.transaction_id
+    TransactionRecord,
+    |this: &TransactionRecord| format!("transaction-{:020}", this.transaction.transaction_id),
+    |this: &TransactionRecord| this.transaction.transaction_id
 );
@@ -1,6 +1,5 @@
 use std::result;
 
-use sled::CompareAndSwapError;
 use thiserror::Error;
 use transaction::client::Client;
 
@@ -11,9 +10,22 @@ pub enum Error {
     #[error(transparent)]
     OpeningStorage(#[from] OpeningStorage),
     #[error(transparent)]
+    UnsupportedFormat(#[from] UnsupportedFormat),
+    #[error(transparent)]
     Data(#[from] Data),
 }
 
+impl Error {
+    /// Stable, machine readable identifier of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::OpeningStorage(_) => "opening_storage",
+            Error::UnsupportedFormat(_) => "unsupported_format",
+            Error::Data(e) => e.code(),
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 #[error("failed to open storage: {message}")]
 pub struct OpeningStorage {
@@ -22,12 +34,22 @@ pub struct OpeningStorage {
     pub source: sled::Error,
 }
 
+/// The database was written with keys laid out differently than this version reads them.
+#[derive(Error, Debug)]
+#[error("database uses key format {found} but only format {expected} is supported, recreate it")]
+pub struct UnsupportedFormat {
+    /// `0` for databases written before the format was recorded.
+    pub found: u64,
+    pub expected: u64,
+}
+
 #[derive(Error, Debug)]
 pub enum Data {
     #[error("{0}")]
     Sled(String, #[source] sled::Error),
-    #[error("conflicting while updating data")]
-    Conflict(#[from] CompareAndSwapError),
+    /// A concurrent writer got in the way every time, see [`crate::RetryPolicy`].
+    #[error("conflicting with concurrent writes after {0} attempts")]
+    Conflict(usize),
     #[error("failed to serialize data")]
     Serialization(#[from] serde_json::Error),
     #[error("key not found {0}")]
@@ -37,3 +59,64 @@ pub enum Data {
     #[error("transaction cannot transition from {0} to {1}")]
     InvalidTransition(String, String),
 }
+
+impl Data {
+    /// Stable, machine readable identifier of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Data::Sled(..) => "storage_failure",
+            Data::Conflict(_) => "conflict",
+            Data::Serialization(_) => "serialization_failure",
+            Data::KeyNotFound(_) => "key_not_found",
+            Data::TransactionNotFoundForClient(_) => "transaction_not_found_for_client",
+            Data::InvalidTransition(..) => "invalid_transition",
+        }
+    }
+}
+
+/// Error raised by the operations available inside [`crate::Storage::transaction`].
+#[derive(Error, Debug)]
+pub enum Unabortable {
+    #[error("transaction conflicted with a concurrent write")]
+    Conflict,
+    #[error(transparent)]
+    Data(#[from] Data),
+}
+
+/// Error returned by the closure given to [`crate::Storage::transaction`].
+///
+/// `Conflict` makes the backend run the closure again while `Data` and `Abort` roll back every
+/// write done so far.
+#[derive(Error, Debug)]
+pub enum Conflictable<E> {
+    #[error("transaction conflicted with a concurrent write")]
+    Conflict,
+    #[error(transparent)]
+    Data(Data),
+    #[error("{0}")]
+    Abort(E),
+}
+
+impl<E> From<Unabortable> for Conflictable<E> {
+    fn from(error: Unabortable) -> Self {
+        match error {
+            Unabortable::Conflict => Self::Conflict,
+            Unabortable::Data(e) => Self::Data(e),
+        }
+    }
+}
+
+impl<E> From<Data> for Conflictable<E> {
+    fn from(error: Data) -> Self {
+        Self::Data(error)
+    }
+}
+
+/// Outcome of a [`crate::Storage::transaction`] that did not commit.
+#[derive(Error, Debug)]
+pub enum TransactionError<E> {
+    #[error("{0}")]
+    Abort(E),
+    #[error(transparent)]
+    Storage(#[from] Error),
+}
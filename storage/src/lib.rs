@@ -1,12 +1,61 @@
-use std::result;
+use std::{
+    fmt::{Debug, Display},
+    result,
+};
 
-use crate::errors::Data;
-pub use crate::errors::{Error, Result};
+use futures::stream::BoxStream;
+
+use crate::errors::{Conflictable, Data, TransactionError, Unabortable};
+pub use crate::{
+    errors::{Error, Result},
+    retry::RetryPolicy,
+};
 
 pub mod entities;
 pub mod errors;
+pub mod memory;
+mod retry;
 pub mod sled;
 
+/// Backend agnostic operations the services need from a key value store.
+pub trait Storage: Display + Send + Sync {
+    type Transaction<'a>: StorageTransaction
+    where
+        Self: 'a;
+
+    /// Stores `entity` if its primary key is not taken yet, otherwise stores whatever `update_fn`
+    /// returns when called with the existing entity and `entity`.
+    ///
+    /// Fails with [`Data::Conflict`] when a concurrent writer changed it first.
+    fn create_or_update<T, F>(&self, entity: T, update_fn: F) -> Result<T>
+    where
+        T: ToFromStorage,
+        F: Fn(&T, &T) -> result::Result<T, Data>;
+
+    /// Fetches the entity sharing the primary key of `partial`.
+    fn get<T: ToFromStorage>(&self, partial: &T) -> Result<T>;
+
+    /// Streams every entity whose primary key starts with `prefix`, sorted by primary key.
+    fn list<T: ToFromStorage + Debug + 'static>(&self, prefix: &str) -> BoxStream<'_, Result<T>>;
+
+    /// Runs `f` so that either all of its writes are committed or none of them is, no matter in
+    /// which partitions the entities live.
+    ///
+    /// Fails with [`Data::Conflict`] as soon as `f` conflicts with a concurrent writer, see
+    /// [`RetryPolicy::transaction`] to run it again.
+    fn transaction<R, E, F>(&self, f: F) -> result::Result<R, TransactionError<E>>
+    where
+        F: Fn(&Self::Transaction<'_>) -> result::Result<R, Conflictable<E>>;
+}
+
+/// Operations available inside [`Storage::transaction`], nothing written here is visible outside
+/// of it before it commits.
+pub trait StorageTransaction {
+    fn get<T: ToFromStorage>(&self, partial: &T) -> result::Result<T, Unabortable>;
+
+    fn insert<T: ToFromStorage>(&self, entity: &T) -> result::Result<(), Unabortable>;
+}
+
 pub trait ToStorage {
     fn to_bytes(&self) -> Vec<u8>;
 }
@@ -0,0 +1,161 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Display, Formatter},
+    result,
+    sync::RwLock,
+};
+
+use futures::{stream, stream::BoxStream, StreamExt};
+
+use crate::{
+    errors::{Conflictable, Data, Result, TransactionError, Unabortable},
+    Storage, StorageTransaction, ToFromStorage,
+};
+
+/// Storage kept in a [`HashMap`], useful for tests and short lived runs that do not need to
+/// touch the disk.
+///
+/// Entities are kept encoded the same way other backends keep them, so both behave alike.
+#[derive(Default)]
+pub struct Memory {
+    entities: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn create_or_update_internal<T, F>(&self, entity: T, update_fn: F) -> result::Result<T, Data>
+    where
+        T: ToFromStorage,
+        F: FnOnce(&T, &T) -> result::Result<T, Data>,
+    {
+        let mut entities = self
+            .entities
+            .write()
+            .expect("memory storage lock is poisoned");
+        let primary_key = entity.primary_key();
+        let new = if let Some(existing) = entities.get(&primary_key) {
+            let decoded_existing = T::from_bytes(existing)?;
+            update_fn(&decoded_existing, &entity)?
+        } else {
+            entity
+        };
+        entities.insert(primary_key, new.to_bytes());
+        Ok(new)
+    }
+
+    fn get_internal<T: ToFromStorage>(&self, partial: &T) -> result::Result<T, Data> {
+        let entities = self
+            .entities
+            .read()
+            .expect("memory storage lock is poisoned");
+        let primary_key = partial.primary_key();
+        if let Some(data) = entities.get(&primary_key) {
+            T::from_bytes(data)
+        } else {
+            Err(Data::KeyNotFound(primary_key))
+        }
+    }
+
+    fn list_internal<T: ToFromStorage>(&self, prefix: &str) -> Vec<result::Result<T, Data>> {
+        let entities = self
+            .entities
+            .read()
+            .expect("memory storage lock is poisoned");
+        let mut matching: Vec<_> = entities
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .collect();
+        matching.sort_unstable_by_key(|(key, _)| *key);
+        matching
+            .into_iter()
+            .map(|(_, data)| T::from_bytes(data))
+            .collect()
+    }
+}
+
+impl Storage for Memory {
+    type Transaction<'a> = MemoryTransaction<'a>;
+
+    fn create_or_update<T, F>(&self, entity: T, update_fn: F) -> Result<T>
+    where
+        T: ToFromStorage,
+        F: Fn(&T, &T) -> result::Result<T, Data>,
+    {
+        Ok(self.create_or_update_internal(entity, update_fn)?)
+    }
+
+    fn get<T: ToFromStorage>(&self, partial: &T) -> Result<T> {
+        Ok(self.get_internal(partial)?)
+    }
+
+    fn list<T: ToFromStorage + Debug + 'static>(&self, prefix: &str) -> BoxStream<'_, Result<T>> {
+        let entities = self.list_internal(prefix);
+        stream::iter(entities)
+            .map(|entity| entity.map_err(|e| e.into()))
+            .boxed()
+    }
+
+    fn transaction<R, E, F>(&self, f: F) -> result::Result<R, TransactionError<E>>
+    where
+        F: Fn(&Self::Transaction<'_>) -> result::Result<R, Conflictable<E>>,
+    {
+        // Holding the write lock for the whole transaction means nobody can conflict with it.
+        let mut entities = self
+            .entities
+            .write()
+            .expect("memory storage lock is poisoned");
+        let transaction = MemoryTransaction {
+            committed: &entities,
+            staged: Default::default(),
+        };
+        match f(&transaction) {
+            Ok(rv) => {
+                let staged = transaction.staged.into_inner();
+                entities.extend(staged);
+                Ok(rv)
+            }
+            Err(Conflictable::Conflict) => Err(TransactionError::Storage(Data::Conflict(1).into())),
+            Err(Conflictable::Data(e)) => Err(TransactionError::Storage(e.into())),
+            Err(Conflictable::Abort(e)) => Err(TransactionError::Abort(e)),
+        }
+    }
+}
+
+/// Writes done inside a [`Memory`] transaction, applied on top of the committed entities only
+/// once it succeeds.
+pub struct MemoryTransaction<'a> {
+    committed: &'a HashMap<String, Vec<u8>>,
+    staged: RefCell<HashMap<String, Vec<u8>>>,
+}
+
+impl StorageTransaction for MemoryTransaction<'_> {
+    fn get<T: ToFromStorage>(&self, partial: &T) -> result::Result<T, Unabortable> {
+        let primary_key = partial.primary_key();
+        let staged = self.staged.borrow();
+        if let Some(data) = staged
+            .get(&primary_key)
+            .or_else(|| self.committed.get(&primary_key))
+        {
+            Ok(T::from_bytes(data)?)
+        } else {
+            Err(Data::KeyNotFound(primary_key).into())
+        }
+    }
+
+    fn insert<T: ToFromStorage>(&self, entity: &T) -> result::Result<(), Unabortable> {
+        self.staged
+            .borrow_mut()
+            .insert(entity.primary_key(), entity.to_bytes());
+        Ok(())
+    }
+}
+
+impl Display for Memory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("memory")
+    }
+}
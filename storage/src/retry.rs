@@ -0,0 +1,63 @@
+use std::{result, time::Duration};
+
+use tokio::time;
+use tracing::debug;
+
+use crate::{
+    errors::{Conflictable, Data, TransactionError},
+    Error, Storage,
+};
+
+/// How a [`Storage::transaction`] that lost a race against another writer is run again.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total number of tries, including the first one, before giving up with [`Data::Conflict`].
+    pub attempts: usize,
+    /// Time waited before the first retry, doubled on every following one.
+    pub initial_backoff: Duration,
+    /// Upper bound for the time waited between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 10,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs `f` in a transaction of `storage`, running it again whenever it conflicted with a
+    /// concurrent writer. The wait between tries does not block the thread of the caller.
+    pub async fn transaction<S, R, E, F>(
+        &self,
+        storage: &S,
+        f: F,
+    ) -> result::Result<R, TransactionError<E>>
+    where
+        S: Storage,
+        F: Fn(&S::Transaction<'_>) -> result::Result<R, Conflictable<E>>,
+    {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match storage.transaction(&f) {
+                Err(TransactionError::Storage(Error::Data(Data::Conflict(_))))
+                    if attempt < self.attempts =>
+                {
+                    debug!(attempt, ?backoff, "transaction conflicted, retrying");
+                    time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    attempt += 1;
+                }
+                Err(TransactionError::Storage(Error::Data(Data::Conflict(_)))) => {
+                    return Err(Error::from(Data::Conflict(attempt)).into())
+                }
+                result => return result,
+            }
+        }
+    }
+}
@@ -1,34 +1,74 @@
 use std::{
+    cell::Cell,
+    cmp::Reverse,
+    collections::BinaryHeap,
+    convert::Infallible,
     fmt::{Debug, Display, Formatter},
-    result,
+    fs::File,
+    path::{Path, PathBuf},
+    result, thread,
+    time::{Duration, Instant},
 };
 
 use async_stream::stream;
-use futures::{Stream, TryStreamExt};
-use sled::{Mode, Tree};
+use fs2::FileExt;
+use futures::{stream::BoxStream, Stream, StreamExt, TryStreamExt};
+use sled::{
+    transaction::{
+        ConflictableTransactionError, TransactionError as SledTransactionError, TransactionalTree,
+        UnabortableTransactionError,
+    },
+    Db, Mode, Transactional, Tree,
+};
 use sync::mpsc;
 use tokio::{sync, task};
+use tracing::error;
 
 use crate::{
-    errors::{Data, OpeningStorage, Result},
-    ToFromStorage,
+    errors::{
+        Conflictable, Data, OpeningStorage, Result, TransactionError, Unabortable,
+        UnsupportedFormat,
+    },
+    Storage, StorageTransaction, ToFromStorage,
 };
 
 const DEFAULT_NUMBER_OF_SHARDS: usize = 10;
 
+/// Version of the layout of the keys, to be bumped whenever an entity changes its primary key.
+pub const FORMAT_VERSION: u64 = 1;
+const FORMAT_VERSION_KEY: &str = "format-version";
+/// Longest time dropping a [`Sled`] waits for the lock of its database to be released.
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct Sled {
+    /// Only `None` while being dropped.
+    db: Option<Db>,
+    /// Directory of databases kept between runs.
+    path: Option<PathBuf>,
     number_of_shards: usize,
     shards: Vec<Tree>,
 }
 
 impl Sled {
+    /// Opens a temporary database that is removed once it is dropped.
     pub fn new() -> Result<Self> {
-        Ok(Self::new_internal()?)
+        Self::new_internal(sled::Config::new().temporary(true), None)
+    }
+
+    /// Opens (or creates) a database stored at `path`, keeping its data between runs.
+    ///
+    /// Fails with [`UnsupportedFormat`] when the database was written with another
+    /// [`FORMAT_VERSION`], since its entities would not be found under their current keys.
+    ///
+    /// The database is released once the returned value is dropped, so it can be opened again
+    /// right after.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        Self::new_internal(sled::Config::new().path(&path), Some(path))
     }
 
-    fn new_internal() -> result::Result<Self, OpeningStorage> {
-        let db = sled::Config::new()
-            .temporary(true)
+    fn new_internal(config: sled::Config, path: Option<PathBuf>) -> Result<Self> {
+        let db = config
             .mode(Mode::HighThroughput)
             .open()
             .map_err(|e| OpeningStorage {
@@ -42,55 +82,69 @@ impl Sled {
             message: "failed to open database trees".into(),
             source: e,
         })?;
+        Self::check_format(&db, &shards)?;
         Ok(Self {
+            db: Some(db),
+            path,
             number_of_shards: DEFAULT_NUMBER_OF_SHARDS,
             shards,
         })
     }
 
-    fn get_shard(&self, partition: usize) -> &sled::Tree {
-        let shard_number = partition % self.number_of_shards;
-        // This should be safe because it is a circular array
-        unsafe { self.shards.get_unchecked(shard_number) }
-    }
-
-    pub fn create_or_update<T, F>(&self, entity: T, update_fn: F) -> Result<T>
-    where
-        T: ToFromStorage,
-        F: FnOnce(&T, &T) -> result::Result<T, Data>,
-    {
-        Ok(self.create_or_update_internal(entity, update_fn)?)
+    /// Records [`FORMAT_VERSION`] in empty databases and refuses the ones written with another.
+    fn check_format(db: &Db, shards: &[Tree]) -> Result<()> {
+        let found = db.get(FORMAT_VERSION_KEY).map_err(|e| OpeningStorage {
+            message: "failed to read database format".into(),
+            source: e,
+        })?;
+        let found = match found {
+            Some(found) => {
+                let found: [u8; 8] = found.as_ref().try_into().unwrap_or_default();
+                u64::from_be_bytes(found)
+            }
+            None if shards.iter().all(Tree::is_empty) => {
+                db.insert(FORMAT_VERSION_KEY, &FORMAT_VERSION.to_be_bytes())
+                    .map_err(|e| OpeningStorage {
+                        message: "failed to write database format".into(),
+                        source: e,
+                    })?;
+                FORMAT_VERSION
+            }
+            None => 0,
+        };
+        if found != FORMAT_VERSION {
+            return Err(UnsupportedFormat {
+                found,
+                expected: FORMAT_VERSION,
+            }
+            .into());
+        }
+        Ok(())
     }
 
-    fn create_or_update_internal<T, F>(&self, entity: T, update_fn: F) -> result::Result<T, Data>
-    where
-        T: ToFromStorage,
-        F: FnOnce(&T, &T) -> result::Result<T, Data>,
-    {
-        let shard = self.get_shard(entity.partition());
-        let primary_key = &entity.primary_key();
-        let existing = shard
-            .get(&primary_key)
-            .map_err(|e| Data::Sled(format!("failed to get data for {}", primary_key), e))?;
-        let existing = if let Some(existing) = existing {
-            existing
-        } else {
-            let encoded_entity = entity.to_bytes();
-            shard
-                .insert(primary_key, encoded_entity)
-                .map_err(|e| Data::Sled("failed to insert data".to_string(), e))?;
-            return Ok(entity);
+    /// Waits for the background threads of sled, which hold on to the database a little while
+    /// after it is dropped, to release the lock it takes on `path`.
+    fn wait_released(path: &Path) {
+        let Ok(file) = File::open(path.join("db")) else {
+            return;
         };
-        let decoded_existing = T::from_bytes(existing.as_ref())?;
-        let new = update_fn(&decoded_existing, &entity)?;
-        shard
-            .compare_and_swap(primary_key, Some(existing), Some(new.to_bytes()))
-            .map_err(|e| Data::Sled("sled configuration error".into(), e))??;
-        Ok(new)
+        let started = Instant::now();
+        while file.try_lock_exclusive().is_err() {
+            if started.elapsed() > RELEASE_TIMEOUT {
+                error!("database at {} is still locked", path.display());
+                return;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        if let Err(e) = file.unlock() {
+            error!("failed to unlock database: {}", e);
+        }
     }
 
-    pub fn get<T: ToFromStorage>(&self, partial: &T) -> Result<T> {
-        Ok(self.get_internal(partial)?)
+    fn get_shard(&self, partition: usize) -> &sled::Tree {
+        let shard_number = partition % self.number_of_shards;
+        // This should be safe because it is a circular array
+        unsafe { self.shards.get_unchecked(shard_number) }
     }
 
     fn get_internal<T: ToFromStorage>(&self, partial: &T) -> result::Result<T, Data> {
@@ -106,34 +160,49 @@ impl Sled {
         }
     }
 
-    pub async fn list<T: ToFromStorage + Debug + 'static>(
-        &self,
-        prefix: &'static str,
-    ) -> impl Stream<Item = Result<T>> + '_ {
-        self.list_internal(prefix).await.map_err(|e| e.into())
-    }
-
-    pub async fn list_internal<'a, T: ToFromStorage + Debug + 'static>(
+    fn list_internal<T: ToFromStorage + Debug + 'static>(
         &self,
-        prefix: &'static str,
-    ) -> impl Stream<Item = result::Result<T, Data>> + 'a {
+        prefix: &str,
+    ) -> impl Stream<Item = result::Result<T, Data>> {
         let shards: Vec<Tree> = self.shards.to_vec();
-        let (tx, mut rx) = mpsc::channel(10);
-        let handler = task::spawn_blocking(move || {
-            let tx = tx.clone();
-            for shard in shards {
-                let iter = shard.scan_prefix(prefix).values();
-                for e in iter {
-                    let rv = e
-                        .map_err(|e| {
-                            Data::Sled(format!("failed to list keys from prefix {}", prefix), e)
-                        })
-                        .and_then(|e| T::from_bytes(e.as_ref()));
-                    tx.blocking_send(rv).expect("failed to send results");
-                }
-            }
-        });
+        let prefix = prefix.to_string();
         stream! {
+            let (tx, mut rx) = mpsc::channel(10);
+            let handler = task::spawn_blocking(move || {
+                // Each shard is sorted by key, so always taking the smallest key among the shards
+                // heads keeps the whole output sorted without loading it.
+                let mut scans: Vec<_> = shards
+                    .iter()
+                    .map(|shard| shard.scan_prefix(&prefix))
+                    .collect();
+                let mut heads = BinaryHeap::new();
+                // Returns false once nobody is listening anymore
+                let mut advance = |shard: usize, heads: &mut BinaryHeap<_>| match scans[shard].next() {
+                    Some(Ok((key, value))) => {
+                        heads.push(Reverse((key, shard, value)));
+                        true
+                    }
+                    Some(Err(e)) => tx
+                        .blocking_send(Err(Data::Sled(
+                            format!("failed to list keys from prefix {}", prefix),
+                            e,
+                        )))
+                        .is_ok(),
+                    None => true,
+                };
+                for shard in 0..shards.len() {
+                    if !advance(shard, &mut heads) {
+                        return;
+                    }
+                }
+                while let Some(Reverse((_, shard, value))) = heads.pop() {
+                    if tx.blocking_send(T::from_bytes(value.as_ref())).is_err()
+                        || !advance(shard, &mut heads)
+                    {
+                        return;
+                    }
+                }
+            });
             while let Some(result) = rx.recv().await {
                 yield result;
             }
@@ -142,6 +211,129 @@ impl Sled {
     }
 }
 
+impl Storage for Sled {
+    type Transaction<'a> = SledTransaction<'a>;
+
+    fn create_or_update<T, F>(&self, entity: T, update_fn: F) -> Result<T>
+    where
+        T: ToFromStorage,
+        F: Fn(&T, &T) -> result::Result<T, Data>,
+    {
+        let updated =
+            self.transaction::<_, Infallible, _>(|transaction| match transaction.get(&entity) {
+                Ok(existing) => {
+                    let new = update_fn(&existing, &entity)?;
+                    transaction.insert(&new)?;
+                    Ok(Some(new))
+                }
+                Err(Unabortable::Data(Data::KeyNotFound(_))) => {
+                    transaction.insert(&entity)?;
+                    Ok(None)
+                }
+                Err(e) => Err(e.into()),
+            });
+        match updated {
+            Ok(updated) => Ok(updated.unwrap_or(entity)),
+            Err(TransactionError::Storage(e)) => Err(e),
+            Err(TransactionError::Abort(never)) => match never {},
+        }
+    }
+
+    fn get<T: ToFromStorage>(&self, partial: &T) -> Result<T> {
+        Ok(self.get_internal(partial)?)
+    }
+
+    fn list<T: ToFromStorage + Debug + 'static>(&self, prefix: &str) -> BoxStream<'_, Result<T>> {
+        self.list_internal(prefix).map_err(|e| e.into()).boxed()
+    }
+
+    fn transaction<R, E, F>(&self, f: F) -> result::Result<R, TransactionError<E>>
+    where
+        F: Fn(&Self::Transaction<'_>) -> result::Result<R, Conflictable<E>>,
+    {
+        // sled would run `f` again on conflicts without any limit nor pause, give up on the first
+        // one instead and leave retrying to RetryPolicy
+        let attempted = Cell::new(false);
+        self.shards
+            .as_slice()
+            .transaction(|shards| {
+                if attempted.replace(true) {
+                    return Err(ConflictableTransactionError::Abort(Conflictable::Conflict));
+                }
+                f(&SledTransaction { shards }).map_err(ConflictableTransactionError::Abort)
+            })
+            .map_err(|e| match e {
+                SledTransactionError::Abort(Conflictable::Abort(e)) => TransactionError::Abort(e),
+                SledTransactionError::Abort(Conflictable::Data(e)) => {
+                    TransactionError::Storage(e.into())
+                }
+                SledTransactionError::Abort(Conflictable::Conflict) => {
+                    TransactionError::Storage(Data::Conflict(1).into())
+                }
+                SledTransactionError::Storage(e) => TransactionError::Storage(
+                    Data::Sled("failed to commit transaction".into(), e).into(),
+                ),
+            })
+    }
+}
+
+/// View over every shard of a [`Sled`] database inside a transaction.
+pub struct SledTransaction<'a> {
+    shards: &'a [TransactionalTree],
+}
+
+impl SledTransaction<'_> {
+    fn get_shard(&self, partition: usize) -> &TransactionalTree {
+        &self.shards[partition % self.shards.len()]
+    }
+
+    fn unabortable(error: UnabortableTransactionError, message: String) -> Unabortable {
+        match error {
+            UnabortableTransactionError::Conflict => Unabortable::Conflict,
+            UnabortableTransactionError::Storage(e) => Data::Sled(message, e).into(),
+        }
+    }
+}
+
+impl StorageTransaction for SledTransaction<'_> {
+    fn get<T: ToFromStorage>(&self, partial: &T) -> result::Result<T, Unabortable> {
+        let shard = self.get_shard(partial.partition());
+        let primary_key = partial.primary_key();
+        let data = shard.get(primary_key.as_bytes()).map_err(|e| {
+            Self::unabortable(e, format!("failed to get data for key {}", primary_key))
+        })?;
+        if let Some(data) = data {
+            Ok(T::from_bytes(data.as_ref())?)
+        } else {
+            Err(Data::KeyNotFound(primary_key).into())
+        }
+    }
+
+    fn insert<T: ToFromStorage>(&self, entity: &T) -> result::Result<(), Unabortable> {
+        let shard = self.get_shard(entity.partition());
+        let primary_key = entity.primary_key();
+        shard
+            .insert(primary_key.as_bytes(), entity.to_bytes())
+            .map_err(|e| Self::unabortable(e, "failed to insert data".into()))?;
+        Ok(())
+    }
+}
+
+impl Drop for Sled {
+    fn drop(&mut self) {
+        // Persistent databases would otherwise lose whatever was written since the last
+        // background flush.
+        if let Some(Err(e)) = self.db.as_ref().map(|db| db.flush()) {
+            error!("failed to flush database: {}", e);
+        }
+        self.shards.clear();
+        drop(self.db.take());
+        if let Some(path) = &self.path {
+            Self::wait_released(path);
+        }
+    }
+}
+
 impl Display for Sled {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str("sled")
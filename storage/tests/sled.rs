@@ -0,0 +1,168 @@
+use std::{cell::Cell, sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+use storage::{
+    errors::{Conflictable, Data, TransactionError, Unabortable, UnsupportedFormat},
+    sled::{Sled, SledTransaction, FORMAT_VERSION},
+    Error, RetryPolicy, Storage, StorageTransaction,
+};
+use transaction::client::ClientPosition;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_updates_converge() {
+    let storage = Arc::new(Sled::new().expect("failed to open storage"));
+    let retry_policy = RetryPolicy {
+        attempts: 1000,
+        initial_backoff: Duration::from_micros(10),
+        max_backoff: Duration::from_millis(1),
+    };
+    let writers = 8;
+    let updates_per_writer = 50;
+
+    let handles: Vec<_> = (0..writers)
+        .map(|_| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                for _ in 0..updates_per_writer {
+                    retry_policy
+                        .transaction(storage.as_ref(), |transaction| {
+                            let change = ClientPosition {
+                                client: 1,
+                                available: 1.into(),
+                                ..Default::default()
+                            };
+                            let new = match transaction.get(&change) {
+                                Ok(mut old) => {
+                                    old.available += change.available;
+                                    old
+                                }
+                                Err(Unabortable::Data(Data::KeyNotFound(_))) => change,
+                                Err(e) => return Err(e.into()),
+                            };
+                            transaction.insert(&new)?;
+                            Ok::<_, Conflictable<()>>(())
+                        })
+                        .await
+                        .expect("conflicting update should have been retried");
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.expect("writer panicked");
+    }
+
+    let position = storage
+        .get(&ClientPosition {
+            client: 1,
+            ..Default::default()
+        })
+        .expect("position should exist");
+    assert_eq!(position.available, (writers * updates_per_writer).into());
+}
+
+#[tokio::test]
+async fn conflicts_are_retried_a_bounded_number_of_times() {
+    let storage = Sled::new().expect("failed to open storage");
+    let retry_policy = RetryPolicy {
+        attempts: 3,
+        initial_backoff: Duration::from_micros(10),
+        max_backoff: Duration::from_millis(1),
+    };
+    // Conflicts on every try but the `succeeding_at`th one
+    let tries = Cell::new(0);
+    let conflicting = |succeeding_at: usize| {
+        let tries = &tries;
+        move |_: &SledTransaction<'_>| {
+            tries.set(tries.get() + 1);
+            if tries.get() == succeeding_at {
+                Ok(())
+            } else {
+                Err(Conflictable::<()>::Conflict)
+            }
+        }
+    };
+
+    retry_policy
+        .transaction(&storage, conflicting(3))
+        .await
+        .expect("transaction should succeed on its last try");
+    assert_eq!(tries.replace(0), 3);
+
+    match retry_policy.transaction(&storage, conflicting(4)).await {
+        Err(TransactionError::Storage(Error::Data(Data::Conflict(3)))) => {}
+        other => panic!("retries should have been exhausted, not {:?}", other),
+    }
+    assert_eq!(tries.get(), 3);
+}
+
+#[tokio::test]
+async fn list_is_sorted_across_shards() {
+    let storage = Sled::new().expect("failed to open storage");
+    for client in (1..=25).rev() {
+        storage
+            .create_or_update(
+                ClientPosition {
+                    client,
+                    ..Default::default()
+                },
+                |_, new| Ok(new.clone()),
+            )
+            .expect("failed to store position");
+    }
+
+    let clients: Vec<_> = storage
+        .list::<ClientPosition>("client-position-")
+        .map_ok(|position| position.client)
+        .try_collect()
+        .await
+        .expect("failed to list positions");
+    assert_eq!(clients, (1..=25).collect::<Vec<_>>());
+}
+
+/// Opens the database at `path` once sled released it after writing to it directly.
+#[test]
+fn refuses_database_written_before_format_version() {
+    let directory = tempfile::tempdir().expect("failed to create database directory");
+    {
+        // Positions used to be keyed by client only
+        let db = sled::open(directory.path()).expect("failed to open database");
+        db.open_tree("db-shard-1")
+            .and_then(|shard| shard.insert("client-position-1", "{}"))
+            .expect("failed to write old position");
+        db.flush().expect("failed to flush database");
+    }
+
+    match Sled::open(directory.path()) {
+        Err(Error::UnsupportedFormat(UnsupportedFormat { found: 0, expected })) => {
+            assert_eq!(expected, FORMAT_VERSION)
+        }
+        other => panic!("old database should be refused, not {:?}", other.err()),
+    }
+}
+
+#[test]
+fn refuses_database_of_another_format_version() {
+    let directory = tempfile::tempdir().expect("failed to create database directory");
+    {
+        let db = sled::open(directory.path()).expect("failed to open database");
+        db.insert("format-version", &(FORMAT_VERSION + 1).to_be_bytes())
+            .expect("failed to write format version");
+        db.flush().expect("failed to flush database");
+    }
+
+    match Sled::open(directory.path()) {
+        Err(Error::UnsupportedFormat(UnsupportedFormat { found, .. })) => {
+            assert_eq!(found, FORMAT_VERSION + 1)
+        }
+        other => panic!("newer database should be refused, not {:?}", other.err()),
+    }
+}
+
+#[test]
+fn reopens_database_of_current_format_version() {
+    let directory = tempfile::tempdir().expect("failed to create database directory");
+    drop(Sled::open(directory.path()).expect("failed to create database"));
+
+    Sled::open(directory.path()).expect("database should be reopened");
+}
@@ -1,11 +1,19 @@
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-pub type Client = u16;
+pub type Client = u64;
 
+/// Currency code as given in the input, like `USD`.
+pub type Currency = String;
+
+/// Balance of a client in one currency. `locked` marks the position a chargeback locked, which
+/// freezes every position of the client until that one is unlocked.
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct ClientPosition {
     pub client: Client,
+    /// `None` for transactions that did not specify a currency.
+    #[serde(default)]
+    pub currency: Option<Currency>,
 
     #[serde(with = "rust_decimal::serde::str")]
     pub total: Decimal,
@@ -14,4 +22,20 @@ pub struct ClientPosition {
     #[serde(with = "rust_decimal::serde::str")]
     pub held: Decimal,
     pub locked: bool,
+    /// Sum of every fee charged.
+    #[serde(default, with = "rust_decimal::serde::str")]
+    pub fees: Decimal,
+    /// Sum of every manual adjustment, negative when more was taken than given.
+    #[serde(default, with = "rust_decimal::serde::str")]
+    pub adjustments: Decimal,
+    /// Who unlocked the account the last time and why, `None` if it never was.
+    #[serde(default)]
+    pub last_unlock: Option<Unlock>,
+}
+
+/// Record of an administrative unlock of a position locked by a chargeback.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Unlock {
+    pub operator: String,
+    pub reason: String,
 }
@@ -7,6 +7,9 @@ pub enum Error {
     #[error("failed to parse a csv line")]
     ParsingCsv(#[from] csv_async::Error),
 
+    #[error("failed to parse a json line")]
+    ParsingJson(#[from] serde_json::Error),
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 }
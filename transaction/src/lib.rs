@@ -1,6 +1,8 @@
 pub use crate::errors::Error;
-pub use crate::parser::{Transaction, TransactionState, TransactionType};
+pub use crate::parser::{Row, Transaction, TransactionId, TransactionState, TransactionType};
+pub use crate::record::{StateChange, TransactionRecord};
 
 pub mod client;
 pub mod errors;
 pub mod parser;
+pub mod record;
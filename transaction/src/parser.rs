@@ -1,30 +1,55 @@
-use std::fmt::Display;
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+};
 
 use async_stream::stream;
-use csv_async::{AsyncReaderBuilder, Trim};
+use csv_async::{AsyncReaderBuilder, StringRecord, Trim};
 use enum_display_derive::Display;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader, ReadBuf};
 use tokio_stream::Stream;
 
-use crate::{client::Client, errors::Result};
+use crate::{
+    client::{Client, Currency},
+    errors::Result,
+};
+
+pub type TransactionId = u64;
 
 #[derive(Debug, Deserialize, Serialize, Display, PartialEq, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
+    /// Moves funds from `client` to `destination`.
+    Transfer,
     Dispute,
     Resolve,
     Chargeback,
+    /// Charged by us, may leave the client owing up to a configured limit.
+    Fee,
+    /// Manual correction with a signed amount, requires a `reason`.
+    Adjustment,
+    /// Unlocks the position of `client` in `currency`, requires an `operator` and a `reason`.
+    /// Its `tx` is ignored.
+    Unlock,
 }
 
-#[derive(Debug, Deserialize, Serialize, Display)]
+/// Where the funds of a transaction stand after a row touched it.
+#[derive(Debug, Deserialize, Serialize, Display, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
 pub enum TransactionState {
     Available,
+    /// Under dispute
     Held,
-    Locked,
+    /// Reversed by a chargeback
+    ChargedBack,
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
@@ -33,9 +58,24 @@ pub struct Transaction {
     pub transaction_type: TransactionType,
     pub client: Client,
     #[serde(rename = "tx")]
-    pub transaction_id: u32,
+    pub transaction_id: TransactionId,
 
     pub amount: Option<Decimal>,
+    /// Optional column, positions are kept per client and currency.
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    /// Client credited by a transfer, other types ignore it.
+    #[serde(default)]
+    pub destination: Option<Client>,
+    /// Why an adjustment or unlock was made, other types ignore it.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Who asked for an unlock, other types ignore it.
+    #[serde(default)]
+    pub operator: Option<String>,
+    /// Line of the input the row was read from, set by whoever reads the input.
+    #[serde(skip)]
+    pub line: Option<usize>,
 }
 
 impl Default for Transaction {
@@ -45,23 +85,213 @@ impl Default for Transaction {
             transaction_id: 10,
             client: 1,
             amount: None,
+            currency: None,
+            destination: None,
+            reason: None,
+            operator: None,
+            line: None,
+        }
+    }
+}
+
+/// A row of the input along with where it was found.
+#[derive(Debug)]
+pub struct Row {
+    /// Line of the input the row starts on, counting from 1.
+    pub line: usize,
+    /// The row as found in the input, without its line terminator.
+    pub raw: String,
+    /// The row read into a transaction, whose [`Transaction::line`] is set.
+    pub transaction: Result<Transaction>,
+}
+
+impl Row {
+    fn new(line: usize, raw: String, transaction: Result<Transaction>) -> Self {
+        let transaction = transaction.map(|transaction| Transaction {
+            line: Some(line),
+            ..transaction
+        });
+        Self {
+            line,
+            raw,
+            transaction,
+        }
+    }
+}
+
+/// What a [`LineTracker`] read that no row claimed yet.
+#[derive(Default)]
+struct Unclaimed {
+    /// Offset of the newline ending each line, and whether the line is empty.
+    lines: VecDeque<(u64, bool)>,
+    /// Bytes read from offset `base` on.
+    bytes: VecDeque<u8>,
+    base: u64,
+}
+
+/// Reads through to `inner`, keeping what it read until a [`RowLocator`] claims it.
+///
+/// The csv reader skips empty lines without counting them and only hands out parsed fields, this
+/// is what tells which line a row really is on and what it looked like.
+struct LineTracker<R> {
+    inner: R,
+    offset: u64,
+    empty: bool,
+    unclaimed: Arc<Mutex<Unclaimed>>,
+}
+
+/// Claims rows out of what a [`LineTracker`] read.
+struct RowLocator {
+    unclaimed: Arc<Mutex<Unclaimed>>,
+    lines: usize,
+}
+
+impl<R> LineTracker<R> {
+    fn new(inner: R) -> (Self, RowLocator) {
+        let unclaimed = Arc::new(Mutex::new(Unclaimed::default()));
+        let tracker = Self {
+            inner,
+            offset: 0,
+            empty: true,
+            unclaimed: unclaimed.clone(),
+        };
+        (
+            tracker,
+            RowLocator {
+                unclaimed,
+                lines: 0,
+            },
+        )
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for LineTracker<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let start = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let mut unclaimed = this
+            .unclaimed
+            .lock()
+            .expect("line tracker lock is poisoned");
+        for &byte in &buf.filled()[start..] {
+            match byte {
+                b'\n' => {
+                    unclaimed.lines.push_back((this.offset, this.empty));
+                    this.empty = true;
+                }
+                b'\r' => {}
+                _ => this.empty = false,
+            }
+            unclaimed.bytes.push_back(byte);
+            this.offset += 1;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl RowLocator {
+    /// Line and text of the row the csv reader read from `start` to `end`. `start` is where the
+    /// previous row ended, so before any empty line in between. Rows must be claimed in order.
+    fn claim(&mut self, start: u64, end: u64) -> (usize, String) {
+        let mut unclaimed = self
+            .unclaimed
+            .lock()
+            .expect("line tracker lock is poisoned");
+        let mut row_start = start;
+        while let Some(&(newline, empty)) = unclaimed.lines.front() {
+            // A newline right at `start` ends the previous row, whose `\r\n` was cut after `\r`
+            if newline > start && !empty {
+                break;
+            }
+            unclaimed.lines.pop_front();
+            self.lines += 1;
+            row_start = row_start.max(newline + 1);
         }
+        let base = unclaimed.base;
+        let end = end.max(base);
+        let row_start = row_start.clamp(base, end);
+        let raw: Vec<u8> = unclaimed
+            .bytes
+            .drain(..(end - base) as usize)
+            .skip((row_start - base) as usize)
+            .collect();
+        unclaimed.base = end;
+        let raw = String::from_utf8_lossy(&raw);
+        (
+            self.lines + 1,
+            raw.trim_end_matches(['\r', '\n']).to_string(),
+        )
     }
 }
 
 impl Transaction {
-    pub async fn from_reader<R>(reader: R) -> impl Stream<Item = Result<Transaction>>
+    /// Reads CSV rows with a header line. Blank lines are skipped but still counted in
+    /// [`Row::line`].
+    pub async fn from_reader<R>(reader: R) -> impl Stream<Item = Row>
     where
         R: AsyncRead + Unpin + Send,
     {
-        let mut deserializer = AsyncReaderBuilder::new()
+        let (reader, mut locator) = LineTracker::new(reader);
+        let mut reader = AsyncReaderBuilder::new()
             .delimiter(b',')
             .trim(Trim::All)
-            .create_deserializer(reader);
+            .create_reader(reader);
         stream! {
-            let stream = deserializer.deserialize::<Transaction>();
-            for await result in stream {
-                yield result.map_err(|e| e.into())
+            let headers = match reader.headers().await {
+                Ok(headers) => headers.clone(),
+                Err(e) => {
+                    yield Row::new(1, String::new(), Err(e.into()));
+                    return;
+                }
+            };
+            let mut record = StringRecord::new();
+            loop {
+                let start = reader.position().byte();
+                let transaction = match reader.read_record(&mut record).await {
+                    Ok(false) => break,
+                    Ok(true) => record.deserialize(Some(&headers)),
+                    Err(e) => Err(e),
+                };
+                let (line, raw) = locator.claim(start, reader.position().byte());
+                // Nothing more can be read after the input itself failed
+                let failed = matches!(&transaction, Err(e) if e.is_io_error());
+                yield Row::new(line, raw, transaction.map_err(|e| e.into()));
+                if failed {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Same as [`Transaction::from_reader`] but for newline delimited JSON, one transaction per
+    /// line using the same field names as the CSV header. Blank lines are skipped but still
+    /// counted in [`Row::line`].
+    pub async fn from_json_lines_reader<R>(reader: R) -> impl Stream<Item = Row>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let mut lines = BufReader::new(reader).lines();
+        stream! {
+            let mut line_number = 0;
+            loop {
+                line_number += 1;
+                match lines.next_line().await {
+                    Ok(Some(line)) if line.trim().is_empty() => continue,
+                    Ok(Some(line)) => {
+                        let transaction = serde_json::from_str(&line).map_err(|e| e.into());
+                        yield Row::new(line_number, line, transaction)
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        yield Row::new(line_number, String::new(), Err(e.into()));
+                        break;
+                    }
+                }
             }
         }
     }
@@ -80,11 +310,82 @@ mod tests {
     async fn parse_transactions() {
         let transactions = include_str!("../../fixtures/big_decimals.csv");
         let cursor = Cursor::new(transactions.as_bytes());
-        let transactions: Result<Vec<Transaction>> =
-            Transaction::from_reader(cursor).await.collect().await;
+        let transactions: Result<Vec<Transaction>> = Transaction::from_reader(cursor)
+            .await
+            .map(|row| row.transaction)
+            .collect()
+            .await;
 
         let transactions = transactions.expect("should have transactions");
 
         assert_eq!(transactions.len(), 5);
     }
+
+    #[test]
+    async fn parse_json_lines() {
+        let transactions = concat!(
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.2345"}"#,
+            "\n\n",
+            r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": 0.5}"#,
+            "\n",
+            r#"{"type": "dispute", "client": 1, "tx": 1}"#,
+        );
+        let cursor = Cursor::new(transactions.as_bytes());
+        let rows: Vec<_> = Transaction::from_json_lines_reader(cursor)
+            .await
+            .collect()
+            .await;
+        let lines: Vec<_> = rows.iter().map(|row| row.line).collect();
+        let rows_raw_first = rows[0].raw.clone();
+        let transactions: Result<Vec<Transaction>> =
+            rows.into_iter().map(|row| row.transaction).collect();
+
+        let transactions = transactions.expect("should have transactions");
+
+        assert_eq!(lines, vec![1, 3, 4]);
+        assert_eq!(
+            rows_raw_first,
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.2345"}"#
+        );
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(transactions[0].amount, Some("1.2345".parse().unwrap()));
+        assert_eq!(transactions[1].amount, Some("0.5".parse().unwrap()));
+        assert_eq!(transactions[1].line, Some(3));
+        assert_eq!(transactions[2].amount, None);
+    }
+
+    #[test]
+    async fn csv_lines_count_blank_lines() {
+        let transactions = concat!(
+            "type,client,tx,amount,reason\n",
+            "\n",
+            "deposit,1,1,1.0,\n",
+            "\r\n",
+            "\n",
+            "nope,1,2,1.0,\n",
+            "adjustment,1,3,1.0,\"over\n\ntwo lines\"\r\n",
+            "deposit, 1\n",
+            "deposit,1,4,1.0,",
+        );
+        let cursor = Cursor::new(transactions.as_bytes());
+        let rows: Vec<_> = Transaction::from_reader(cursor).await.collect().await;
+
+        let lines: Vec<_> = rows.iter().map(|row| row.line).collect();
+        assert_eq!(lines, vec![3, 6, 7, 10, 11]);
+        let raws: Vec<_> = rows.iter().map(|row| row.raw.as_str()).collect();
+        assert_eq!(
+            raws,
+            vec![
+                "deposit,1,1,1.0,",
+                "nope,1,2,1.0,",
+                "adjustment,1,3,1.0,\"over\n\ntwo lines\"",
+                "deposit, 1",
+                "deposit,1,4,1.0,",
+            ]
+        );
+        assert!(rows[3].transaction.is_err());
+        assert!(rows[4].transaction.is_ok());
+        assert!(rows[0].transaction.is_ok());
+        assert!(rows[1].transaction.is_err());
+    }
 }
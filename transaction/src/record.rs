@@ -0,0 +1,46 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::parser::{Transaction, TransactionState, TransactionType};
+
+/// What is stored for every transaction id: the last row that touched it and the kind of row
+/// that opened it, so disputes know which way funds moved in the first place.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct TransactionRecord {
+    pub transaction: Transaction,
+    /// One of [`TransactionType::Deposit`], [`TransactionType::Withdrawal`],
+    /// [`TransactionType::Transfer`], [`TransactionType::Fee`] or [`TransactionType::Adjustment`].
+    pub kind: TransactionType,
+    /// Part of the amount currently under dispute.
+    pub held: Decimal,
+    /// Part of the amount that can still be disputed, resolved funds can be disputed again.
+    pub disputable: Decimal,
+    /// Every row applied to the transaction, oldest first.
+    #[serde(default)]
+    pub history: Vec<StateChange>,
+}
+
+/// A row applied to a transaction and what it did to its funds.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct StateChange {
+    pub transaction_type: TransactionType,
+    /// State `amount` ended up in.
+    pub state: TransactionState,
+    pub amount: Decimal,
+    /// See [`Transaction::line`].
+    pub line: Option<usize>,
+}
+
+impl From<Transaction> for TransactionRecord {
+    fn from(transaction: Transaction) -> Self {
+        let kind = transaction.transaction_type.clone();
+        let disputable = transaction.amount.unwrap_or_default();
+        Self {
+            transaction,
+            kind,
+            held: Decimal::ZERO,
+            disputable,
+            history: vec![],
+        }
+    }
+}